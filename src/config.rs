@@ -0,0 +1,550 @@
+//! Project configuration, read from `blueprint.toml`
+
+use crate::diagnostic::Severity;
+use crate::markup::Tag;
+use anyhow::{Context, Result};
+
+/// Name of the configuration file looked for in the input directory
+pub const FILE_NAME: &str = "blueprint.toml";
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Project {
+    /// Brand shown in the navigation bar
+    pub name: String,
+}
+
+impl Default for Project {
+    fn default() -> Self {
+        Project {
+            name: "Blueprint".to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Default, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Input {
+    /// Source directory, relative to the configuration file
+    pub path: Option<std::path::PathBuf>,
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    /// Leave out blueprints whose front matter has `status: draft`, as for a release build
+    pub exclude_drafts: bool,
+}
+
+/// Where output goes and how generated files are named
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Output {
+    /// Output directory, relative to the configuration file
+    pub path: Option<std::path::PathBuf>,
+    /// Bundle fonts and refuse to write pages referencing external resources
+    pub offline: bool,
+    /// URL schemes links may use; links without a scheme are always allowed
+    pub link_schemes: Vec<String>,
+    pub page_prefix: String,
+    pub requirement_prefix: String,
+    pub tag_prefix: String,
+    /// Directory below the output directory figures are copied into
+    pub asset_dir: String,
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        Output {
+            path: None,
+            offline: false,
+            link_schemes: vec![
+                "http".to_string(),
+                "https".to_string(),
+                "mailto".to_string(),
+            ],
+            page_prefix: "bp_".to_string(),
+            requirement_prefix: "req_".to_string(),
+            tag_prefix: "tag_".to_string(),
+            asset_dir: "assets".to_string(),
+        }
+    }
+}
+
+impl Output {
+    /// File for a blueprint, flattening the directories of its name so pages can share relative
+    /// links
+    pub fn page_file(&self, name: &str) -> String {
+        self.page_prefix.clone() + &name.replace('/', "__") + ".html"
+    }
+
+    pub fn requirement_file(&self, name: &str) -> String {
+        self.requirement_prefix.clone() + &file_stem(name) + ".html"
+    }
+
+    /// Output file of a copied figure, named after its contents
+    pub fn asset_file(&self, source: &std::path::Path, content: &[u8]) -> String {
+        format!(
+            "{}/{}",
+            self.asset_dir,
+            crate::resource::hashed_name(source, content)
+        )
+    }
+
+    /// Whether a link to `url` may be rendered: relative, or using an allowed scheme
+    pub fn allows_link(&self, url: &str) -> bool {
+        // Browsers ignore whitespace and control characters inside a scheme
+        let url = url
+            .chars()
+            .filter(|c| !c.is_ascii_whitespace() && !c.is_control())
+            .collect::<String>();
+        let scheme = match url.split_once(':') {
+            Some((scheme, _))
+                if scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                    && scheme
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || "+.-".contains(c)) =>
+            {
+                scheme
+            }
+            _ => return true,
+        };
+        self.link_schemes
+            .iter()
+            .any(|s| s.eq_ignore_ascii_case(scheme))
+    }
+
+    /// File a tag links to: its tag page, or the page of the requirement it names
+    pub fn tag_file(&self, tag: &Tag) -> String {
+        match tag.sigil {
+            None => self.tag_prefix.clone() + &file_stem(&tag.name) + ".html",
+            Some(_) => self.requirement_file(&tag.name),
+        }
+    }
+}
+
+/// A tag or requirement name as a single file name: directories are flattened as in page files and
+/// characters that file systems or links treat specially become `_`
+fn file_stem(name: &str) -> String {
+    name.replace('/', "__")
+        .chars()
+        .map(|c| match c {
+            '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' | '#' | '%' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Theme {
+    /// One of the embedded themes
+    pub name: String,
+    /// Theme directory with a `theme.toml` manifest, used instead of `name`, relative to the
+    /// configuration file
+    pub path: Option<std::path::PathBuf>,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            name: crate::resource::THEME_DEFAULT_NAME.to_string(),
+            path: None,
+        }
+    }
+}
+
+#[derive(Clone, Default, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Templates {
+    /// Directory whose templates replace the built-in ones of the same file name, relative to the
+    /// configuration file
+    pub path: Option<std::path::PathBuf>,
+}
+
+/// Rendering of one kind of tag
+#[derive(Clone, Default, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TagStyle {
+    /// CSS classes of the tag label
+    pub class: String,
+}
+
+impl TagStyle {
+    fn new(class: &str) -> TagStyle {
+        TagStyle {
+            class: class.to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Tags {
+    /// Whether simple tags get `tag_*.html` pages and a tag index
+    pub pages: bool,
+    pub simple: TagStyle,
+    /// Sigil of a tag declaring its section a requirement
+    pub requirement_sigil: char,
+    pub requires: TagStyle,
+}
+
+impl Default for Tags {
+    fn default() -> Self {
+        Tags {
+            pages: true,
+            simple: TagStyle::new("m-label m-flat m-default"),
+            requirement_sigil: '?',
+            requires: TagStyle::new("m-label m-warning"),
+        }
+    }
+}
+
+/// m.css colors a relation may use
+const COLORS: &[&str] = &[
+    "default", "primary", "success", "warning", "danger", "info", "dim",
+];
+
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// From the tagged section to the requirement it names
+    #[default]
+    Directed,
+    /// Reading the same from both ends, such as a conflict
+    Undirected,
+}
+
+/// A kind of link from a section to a requirement, declared as `[relations.<name>]`
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Relation {
+    /// Character opening its tags, one of [`crate::markup::SIGILS`]
+    pub sigil: char,
+    /// Wording on rendered pages, such as "verifies"
+    pub label: String,
+    /// m.css color of its tag labels
+    #[serde(default = "Relation::default_color")]
+    pub color: String,
+    #[serde(default)]
+    pub direction: Direction,
+    /// Whether a link makes its target count as satisfied
+    #[serde(default)]
+    pub satisfies: bool,
+    /// How a link to an undeclared requirement is reported, `lint.dangling` if unset
+    #[serde(default)]
+    pub dangling: Option<Level>,
+}
+
+impl Relation {
+    fn default_color() -> String {
+        "default".to_string()
+    }
+}
+
+/// Every relation by name; declaring any replaces the built-in `satisfies`
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(transparent)]
+pub struct Relations(pub std::collections::BTreeMap<String, Relation>);
+
+impl Default for Relations {
+    fn default() -> Self {
+        Relations(
+            [(
+                "satisfies".to_string(),
+                Relation {
+                    sigil: '=',
+                    label: "satisfies".to_string(),
+                    color: "success".to_string(),
+                    direction: Direction::Directed,
+                    satisfies: true,
+                    dangling: None,
+                },
+            )]
+            .into(),
+        )
+    }
+}
+
+impl Relations {
+    /// The relation whose tags open with `sigil`, with its name
+    pub fn by_sigil(&self, sigil: char) -> Option<(&String, &Relation)> {
+        self.0.iter().find(|(_, r)| r.sigil == sigil)
+    }
+}
+
+/// Constraints on one tag attribute, declared as `[attributes.<key>]`
+#[derive(Clone, Default, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Attribute {
+    /// Values the attribute may take; any value is accepted when empty
+    pub values: Vec<String>,
+}
+
+impl Attribute {
+    pub fn allows(&self, value: &str) -> bool {
+        self.values.is_empty() || self.values.iter().any(|v| v == value)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+impl Level {
+    /// Severity a problem at this level is reported with, if at all
+    pub fn severity(self) -> Option<Severity> {
+        match self {
+            Level::Allow => None,
+            Level::Warn => Some(Severity::Warning),
+            Level::Deny => Some(Severity::Error),
+        }
+    }
+}
+
+/// How model problems are reported
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Lint {
+    /// A requirement declared more than once
+    pub duplicate: Level,
+    /// A requirement nothing satisfies
+    pub unsatisfied: Level,
+    /// A link to an undeclared requirement, for relations not setting their own level
+    pub dangling: Level,
+    /// A link whose URL scheme is not in `output.link_schemes`; it is rendered as plain text
+    pub link: Level,
+    /// A `<<<` raw HTML block
+    pub raw_html: Level,
+    /// A front matter key outside the schema
+    pub front_matter: Level,
+    /// A tag attribute value outside the ones its `[attributes.<key>]` allows
+    pub attribute: Level,
+}
+
+impl Default for Lint {
+    fn default() -> Self {
+        Lint {
+            duplicate: Level::Warn,
+            unsatisfied: Level::Warn,
+            dangling: Level::Warn,
+            link: Level::Warn,
+            raw_html: Level::Allow,
+            front_matter: Level::Warn,
+            attribute: Level::Warn,
+        }
+    }
+}
+
+#[derive(Clone, Default, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub project: Project,
+    pub input: Input,
+    pub output: Output,
+    pub theme: Theme,
+    pub templates: Templates,
+    pub tags: Tags,
+    pub relations: Relations,
+    /// Tag attributes with restricted values, by key
+    pub attributes: std::collections::BTreeMap<String, Attribute>,
+    pub lint: Lint,
+}
+
+impl Config {
+    /// Read a configuration file, resolving its paths relative to the file
+    pub fn load(file: &std::path::Path) -> Result<Config> {
+        let text =
+            std::fs::read_to_string(file).with_context(|| format!("reading {}", file.display()))?;
+        let mut config: Config =
+            toml::from_str(&text).with_context(|| format!("parsing {}", file.display()))?;
+        let base = file.parent().unwrap_or_else(|| std::path::Path::new(""));
+        for path in [
+            &mut config.input.path,
+            &mut config.output.path,
+            &mut config.theme.path,
+            &mut config.templates.path,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *path = base.join(&*path);
+        }
+        config
+            .validate()
+            .with_context(|| format!("checking {}", file.display()))?;
+        Ok(config)
+    }
+
+    /// Check that every sigil means one thing and every relation color exists
+    pub fn validate(&self) -> Result<()> {
+        let mut sigils = vec![(self.tags.requirement_sigil, "tags.requirement_sigil")];
+        for (name, relation) in &self.relations.0 {
+            anyhow::ensure!(
+                COLORS.contains(&relation.color.as_str()),
+                "relation {} has unknown color {}, expected one of {}",
+                name,
+                relation.color,
+                COLORS.join(", ")
+            );
+            sigils.push((relation.sigil, name.as_str()));
+        }
+        for (n, (sigil, owner)) in sigils.iter().enumerate() {
+            anyhow::ensure!(
+                crate::markup::SIGILS.contains(*sigil),
+                "{} uses {} as a sigil, expected one of {}",
+                owner,
+                sigil,
+                crate::markup::SIGILS
+            );
+            if let Some((_, other)) = sigils[..n].iter().find(|(s, _)| s == sigil) {
+                anyhow::bail!("{} and {} both use the sigil {}", other, owner, sigil);
+            }
+        }
+        Ok(())
+    }
+
+    /// CSS classes of a tag label
+    pub fn tag_class(&self, tag: &Tag) -> String {
+        match tag.sigil {
+            Some(sigil) if sigil == self.tags.requirement_sigil => self.tags.requires.class.clone(),
+            Some(sigil) => match self.relations.by_sigil(sigil) {
+                Some((_, relation)) => format!("m-label m-{}", relation.color),
+                None => self.tags.simple.class.clone(),
+            },
+            None => self.tags.simple.class.clone(),
+        }
+    }
+
+    /// Load `explicit` if given, else the first `blueprint.toml` found in the input directory or
+    /// the working directory, else the defaults
+    pub fn discover(
+        explicit: Option<&std::path::Path>,
+        input: Option<&std::path::Path>,
+    ) -> Result<Config> {
+        if let Some(file) = explicit {
+            anyhow::ensure!(
+                file.is_file(),
+                "configuration file {} does not exist",
+                file.display()
+            );
+            return Config::load(file);
+        }
+        let candidates = input
+            .map(|dir| dir.join(FILE_NAME))
+            .into_iter()
+            .chain(std::iter::once(std::path::PathBuf::from(FILE_NAME)));
+        for file in candidates {
+            if file.is_file() {
+                return Config::load(&file);
+            }
+        }
+        Ok(Config::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let config: Config = toml::from_str(
+            r#"
+            [project]
+            name = "Acme"
+
+            [output]
+            requirement_prefix = "r-"
+
+            [attributes.priority]
+            values = ["low", "high"]
+
+            [lint]
+            unsatisfied = "deny"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.project.name, "Acme");
+        assert_eq!(config.output.requirement_file("x"), "r-x.html");
+        assert_eq!(config.output.page_file("a/b.bp"), "bp_a__b.bp.html");
+        assert_eq!(config.output.requirement_file("net/api"), "r-net__api.html");
+        let tag = Tag {
+            name: "a\\b:c#d".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(config.output.tag_file(&tag), "tag_a_b_c_d.html");
+        assert_eq!(config.lint.unsatisfied, Level::Deny);
+        assert_eq!(config.lint.dangling, Level::Warn);
+        assert!(config.attributes["priority"].allows("high"));
+        assert!(!config.attributes["priority"].allows("urgent"));
+        assert!(Attribute::default().allows("anything"));
+        assert!(toml::from_str::<Config>("[project]\nnmae = \"typo\"").is_err());
+    }
+
+    #[test]
+    fn relations() -> Result<()> {
+        let config = Config::default();
+        config.validate()?;
+        let (name, relation) = config.relations.by_sigil('=').unwrap();
+        assert_eq!((name.as_str(), relation.satisfies), ("satisfies", true));
+        let tag = |sigil| Tag {
+            sigil,
+            ..Default::default()
+        };
+        assert_eq!(config.tag_class(&tag(Some('='))), "m-label m-success");
+        assert_eq!(config.tag_class(&tag(Some('?'))), "m-label m-warning");
+        assert_eq!(config.tag_class(&tag(None)), "m-label m-flat m-default");
+
+        let config: Config =
+            toml::from_str("[relations.refines]\nsigil = \"^\"\nlabel = \"refines\"\n")?;
+        assert!(config.relations.by_sigil('=').is_none());
+        assert_eq!(config.relations.0["refines"].direction, Direction::Directed);
+        config.validate()?;
+        for text in [
+            "[relations.a]\nsigil = \"?\"\nlabel = \"a\"\n",
+            "[relations.a]\nsigil = \"#\"\nlabel = \"a\"\n",
+            "[relations.a]\nsigil = \"!\"\nlabel = \"a\"\ncolor = \"red\"\n",
+            "[relations.a]\nsigil = \"!\"\nlabel = \"a\"\n[relations.b]\nsigil = \"!\"\nlabel = \"b\"\n",
+        ] {
+            assert!(toml::from_str::<Config>(text)?.validate().is_err(), "{}", text);
+        }
+        assert!(toml::from_str::<Config>("[relations.a]\nlabel = \"a\"\n").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn links() {
+        let output = Output::default();
+        for url in ["https://a.b/c", "page.html#x", "/x:y", "MAILTO:a@b", "#top"] {
+            assert!(output.allows_link(url), "{}", url);
+        }
+        for url in [
+            "javascript:alert(1)",
+            " JavaScript:x",
+            "java\tscript:x",
+            "data:text/html,x",
+        ] {
+            assert!(!output.allows_link(url), "{}", url);
+        }
+    }
+
+    #[test]
+    fn discover() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(
+            dir.path().join(FILE_NAME),
+            "[input]\npath = \"docs\"\n[output]\npath = \"site\"\n[templates]\npath = \"brand\"\n",
+        )?;
+        let config = Config::discover(None, Some(dir.path()))?;
+        assert_eq!(config.input.path, Some(dir.path().join("docs")));
+        assert_eq!(config.output.path, Some(dir.path().join("site")));
+        assert_eq!(config.templates.path, Some(dir.path().join("brand")));
+        assert!(Config::discover(Some(&dir.path().join("missing.toml")), None).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,262 @@
+use crate::markup::Location;
+
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A single report about a source file, printed in the style of rustc
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub file: String,
+    pub location: Location,
+    /// The offending source line, if the source is available
+    pub excerpt: Option<String>,
+    pub expected: Vec<String>,
+    pub hint: Option<String>,
+    /// Further context, such as the `!include` chain leading to the file
+    pub notes: Vec<String>,
+}
+
+/// Messages raised by the grammar itself rather than by a failed literal match
+const GRAMMAR_MESSAGES: &[&str] = &["wrong rank", "separator count mismatch"];
+
+impl Diagnostic {
+    pub fn new(severity: Severity, file: &str, location: Location, message: &str) -> Diagnostic {
+        Diagnostic {
+            severity,
+            message: message.to_string(),
+            file: file.to_string(),
+            location,
+            excerpt: None,
+            expected: vec![],
+            hint: None,
+            notes: vec![],
+        }
+    }
+
+    pub fn error(file: &str, location: Location, message: &str) -> Diagnostic {
+        Diagnostic::new(Severity::Error, file, location, message)
+    }
+
+    pub fn warning(file: &str, location: Location, message: &str) -> Diagnostic {
+        Diagnostic::new(Severity::Warning, file, location, message)
+    }
+
+    /// Attach the source line the location points into
+    pub fn with_source(mut self, source: &str) -> Diagnostic {
+        self.excerpt = source
+            .lines()
+            .nth(self.location.line.saturating_sub(1))
+            .map(str::to_string);
+        self
+    }
+
+    pub fn with_hint(mut self, hint: &str) -> Diagnostic {
+        self.hint = Some(hint.to_string());
+        self
+    }
+
+    pub fn with_note(mut self, note: &str) -> Diagnostic {
+        self.notes.push(note.to_string());
+        self
+    }
+
+    /// Turn a grammar failure into a report, pointing at the construct that caused it
+    pub fn from_parse_error(
+        file: &str,
+        source: &str,
+        err: &peg::error::ParseError<peg::str::LineCol>,
+    ) -> Diagnostic {
+        let tokens = err.expected.tokens().collect::<Vec<_>>();
+        let message = tokens
+            .iter()
+            .find(|t| GRAMMAR_MESSAGES.contains(t))
+            .copied();
+        let mut location = Location {
+            start: err.location.offset,
+            end: err.location.offset,
+            line: err.location.line,
+            column: err.location.column,
+        };
+        let mut hint = String::new();
+        match message {
+            Some("wrong rank") => {
+                if let Some((heading, previous)) = Self::find_heading(source, err.location.offset) {
+                    location = heading;
+                    hint = match previous {
+                        Some(prev) => format!(
+                            "heading jumped from {} to {}",
+                            "#".repeat(prev),
+                            "#".repeat(heading.end - heading.start)
+                        ),
+                        None => format!(
+                            "the first heading must be #, not {}",
+                            "#".repeat(heading.end - heading.start)
+                        ),
+                    };
+                }
+            }
+            Some("separator count mismatch") => {
+                hint =
+                    "the row under a table heading needs one `-` cell per heading cell".to_string();
+            }
+            _ => {}
+        }
+        let mut out = Diagnostic::error(file, location, message.unwrap_or("unexpected input"))
+            .with_source(source);
+        out.expected = tokens
+            .iter()
+            .filter(|t| !GRAMMAR_MESSAGES.contains(t))
+            .map(|t| t.to_string())
+            .collect();
+        if hint.is_empty() {
+            out
+        } else {
+            out.with_hint(&hint)
+        }
+    }
+
+    /// Location of the `#` run of the last heading starting before `offset`, and the rank of the
+    /// heading before that one
+    fn find_heading(source: &str, offset: usize) -> Option<(Location, Option<usize>)> {
+        let mut headings = vec![];
+        let mut start = 0;
+        for (n, line) in source.split_inclusive('\n').enumerate() {
+            if start >= offset {
+                break;
+            }
+            let rank = line.chars().take_while(|c| *c == '#').count();
+            if rank > 0 {
+                headings.push((
+                    Location {
+                        start,
+                        end: start + rank,
+                        line: n + 1,
+                        column: 1,
+                    },
+                    rank,
+                ));
+            }
+            start += line.len();
+        }
+        let (heading, _) = headings.pop()?;
+        Some((heading, headings.last().map(|h| h.1)))
+    }
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}: {}", self.severity, self.message)?;
+        let gutter = " ".repeat(self.location.line.to_string().len());
+        write!(f, "{}--> {}", gutter, self.file)?;
+        if self.location.line > 0 {
+            write!(f, ":{}", self.location)?;
+        }
+        if let Some(excerpt) = &self.excerpt {
+            let width = self.location.end.saturating_sub(self.location.start).max(1);
+            write!(f, "\n{} |", gutter)?;
+            write!(f, "\n{} | {}", self.location.line, excerpt)?;
+            write!(
+                f,
+                "\n{} | {}{}",
+                gutter,
+                " ".repeat(self.location.column.saturating_sub(1)),
+                "^".repeat(width)
+            )?;
+        }
+        if !self.expected.is_empty() {
+            write!(
+                f,
+                "\n{} = expected one of: {}",
+                gutter,
+                self.expected.join(", ")
+            )?;
+        }
+        if let Some(hint) = &self.hint {
+            write!(f, "\n{} = hint: {}", gutter, hint)?;
+        }
+        for note in &self.notes {
+            write!(f, "\n{} = note: {}", gutter, note)?;
+        }
+        Ok(())
+    }
+}
+
+/// Every report produced for one run, usable as an error
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Diagnostics(pub Vec<Diagnostic>);
+
+impl std::fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (n, d) in self.0.iter().enumerate() {
+            if n > 0 {
+                writeln!(f)?;
+                writeln!(f)?;
+            }
+            write!(f, "{}", d)?;
+        }
+        Ok(())
+    }
+}
+
+impl Diagnostics {
+    /// Point every report at `file`, e.g. a full path instead of a blueprint name
+    pub fn in_file(mut self, file: &str) -> Diagnostics {
+        for d in &mut self.0 {
+            d.file = file.to_string();
+        }
+        self
+    }
+}
+
+impl std::error::Error for Diagnostics {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heading_jump() {
+        let source = "# a\n### b\ntext\n";
+        let err = crate::markup::Blueprint::parse("a.bp", source)
+            .unwrap_err()
+            .downcast::<Diagnostics>()
+            .unwrap();
+        assert_eq!(err.0.len(), 1);
+        assert_eq!(
+            err.to_string(),
+            "error: wrong rank\n --> a.bp:2:1\n  |\n2 | ### b\n  | ^^^\n  = hint: heading jumped from # to ###"
+        );
+    }
+
+    #[test]
+    fn multiple() {
+        let source = "# a\n### b\n\nsome text\n\n## c\n#### d\n";
+        let err = crate::markup::Blueprint::parse("a.bp", source)
+            .unwrap_err()
+            .downcast::<Diagnostics>()
+            .unwrap();
+        assert_eq!(
+            err.0
+                .iter()
+                .map(|d| (d.message.as_str(), d.location.line))
+                .collect::<Vec<_>>(),
+            vec![("wrong rank", 2), ("wrong rank", 7)]
+        );
+    }
+}
@@ -0,0 +1,403 @@
+//! Canonical text form of a blueprint, as written by `blueprint fmt`
+//!
+//! Parsing the output of [`blueprint`] yields a tree equal to the one it was printed from.
+
+use crate::markup::*;
+
+pub fn blueprint(bp: &Blueprint) -> String {
+    let mut out = String::new();
+    front_matter(&mut out, &bp.meta);
+    for sec in &bp.root.subsections {
+        section(&mut out, sec);
+    }
+    out
+}
+
+/// Valid known keys in schema order, then invalid and unknown ones, each in file order
+fn front_matter(out: &mut String, meta: &Meta) {
+    if meta.is_empty() {
+        return;
+    }
+    out.push_str("---\n");
+    let unknown = meta
+        .invalid
+        .iter()
+        .chain(&meta.unknown)
+        .map(|f| (f.key.as_str(), f.value.clone()));
+    for (key, value) in meta.known().into_iter().chain(unknown) {
+        out.push_str(key);
+        out.push(':');
+        if !value.is_empty() {
+            out.push(' ');
+            out.push_str(&value);
+        }
+        out.push('\n');
+    }
+    out.push_str("---\n");
+}
+
+fn section(out: &mut String, sec: &Section) {
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    heading(out, &sec.heading);
+    for par in &sec.body {
+        out.push('\n');
+        paragraph(out, par);
+    }
+    for sub in &sec.subsections {
+        section(out, sub);
+    }
+}
+
+fn heading(out: &mut String, heading: &Heading) {
+    out.push_str(&"#".repeat(heading.rank));
+    out.push(' ');
+    out.push_str(&heading.text);
+    if let Some(id) = &heading.id {
+        out.push_str(" {#");
+        out.push_str(id);
+        out.push('}');
+    }
+    if !heading.tags.is_empty() {
+        out.push_str(" [");
+        out.push_str(&heading.tags.iter().map(tag).collect::<Vec<_>>().join(","));
+        out.push(']');
+    }
+    out.push('\n');
+}
+
+fn tag(tag: &Tag) -> String {
+    let mut out = tag.sigil.map(String::from).unwrap_or_default() + &tag.name;
+    for (key, value) in &tag.attributes {
+        out.push(' ');
+        out.push_str(key);
+        out.push('=');
+        if value.is_empty() || value.contains([' ', '\t', ',', ']']) {
+            out.push('"');
+            out.push_str(value);
+            out.push('"');
+        } else {
+            out.push_str(value);
+        }
+    }
+    out
+}
+
+fn paragraph(out: &mut String, par: &Paragraph) {
+    match par {
+        Paragraph::Empty => {}
+        Paragraph::Spans(s) => {
+            let start = out.len();
+            spans(out, s);
+            escape_line_start(out, start);
+            out.push('\n');
+        }
+        Paragraph::Block(s) => delimited(out, s, "|"),
+        Paragraph::Note(s) => delimited(out, s, "||"),
+        Paragraph::Table(t) => table(out, t),
+        Paragraph::List(l) => list(out, l, 0),
+        Paragraph::Include(path) => {
+            out.push_str("!include ");
+            out.push_str(path);
+            out.push('\n');
+        }
+        Paragraph::Figure(f) => {
+            out.push_str("![");
+            let caption = f.caption.iter().map(|s| s.node.clone()).collect::<Vec<_>>();
+            inline(out, &caption, "", Some(']'));
+            out.push_str("](");
+            out.push_str(&f.path);
+            out.push_str(")\n");
+        }
+        Paragraph::Html(h) => {
+            out.push_str("<<<\n");
+            out.push_str(h);
+            out.push_str(">>>\n");
+        }
+        Paragraph::Code(language, code) => {
+            out.push_str("```");
+            out.push_str(language);
+            out.push('\n');
+            out.push_str(code);
+            out.push_str("```\n");
+        }
+    }
+}
+
+/// Block and note lines, split back onto the source lines they were parsed from
+fn delimited(out: &mut String, s: &[Located<Span>], delimiter: &str) {
+    for line in s.chunk_by(|a, b| a.location.line == b.location.line) {
+        out.push_str(delimiter);
+        spans(out, line);
+        out.push_str(delimiter);
+        out.push('\n');
+    }
+}
+
+fn table(out: &mut String, t: &Table) {
+    if !t.heading.is_empty() {
+        row(out, &t.heading);
+        out.push_str(&vec!["-"; t.heading.len()].join("|"));
+        out.push('\n');
+    }
+    for r in &t.body {
+        row(out, r);
+    }
+}
+
+fn row(out: &mut String, cells: &[Vec<Located<Span>>]) {
+    let start = out.len();
+    for (n, cell) in cells.iter().enumerate() {
+        if n > 0 {
+            out.push('|');
+        }
+        spans(out, cell);
+    }
+    escape_line_start(out, start);
+    out.push('\n');
+}
+
+fn list(out: &mut String, l: &List, indent: usize) {
+    for (n, item) in l.items.iter().enumerate() {
+        let marker = match l.ordered {
+            true => format!("{}. ", n + 1),
+            false => "- ".to_string(),
+        };
+        out.push_str(&" ".repeat(indent));
+        out.push_str(&marker);
+        match item.task {
+            Some(true) => out.push_str("[x] "),
+            Some(false) => out.push_str("[ ] "),
+            None => {}
+        }
+        let start = out.len();
+        spans(out, &item.spans);
+        if item.task.is_none() && is_task(&out[start..]) {
+            out.insert(start, '\\');
+        }
+        out.push('\n');
+        if let Some(sub) = &item.list {
+            list(out, sub, indent + marker.len());
+        }
+    }
+}
+
+/// Whether text starts with a `[ ]`/`[x]` task checkbox
+fn is_task(text: &str) -> bool {
+    ["[ ]", "[x]", "[X]"].iter().any(|b| text.starts_with(b)) && text[3..].starts_with([' ', '\t'])
+}
+
+/// Escape the start of a line written from `start` that would otherwise parse as a list item, a
+/// figure or an include
+fn escape_line_start(out: &mut String, start: usize) {
+    let line = &out[start..];
+    let indent = line.len() - line.trim_start_matches(' ').len();
+    let rest = &line[indent..];
+    if is_task(rest) || rest.starts_with("![") || rest.starts_with("!include") {
+        out.insert(start + indent, '\\');
+        return;
+    }
+    let digits = rest.chars().take_while(char::is_ascii_digit).count();
+    let marker = match rest.chars().next() {
+        Some('-' | '*') => 0,
+        _ if digits > 0 && rest[digits..].starts_with('.') => digits,
+        _ => return,
+    };
+    if rest[marker + 1..].starts_with([' ', '\t']) {
+        out.insert(start + indent + marker, '\\');
+    }
+}
+
+fn spans(out: &mut String, s: &[Located<Span>]) {
+    inline(
+        out,
+        &s.iter().map(|s| s.node.clone()).collect::<Vec<_>>(),
+        "",
+        None,
+    );
+}
+
+/// A run of spans followed on the same line by `rest`, inside a decorated span closed by `closer`
+fn inline(out: &mut String, s: &[Span], rest: &str, closer: Option<char>) {
+    for (n, span) in s.iter().enumerate() {
+        let after = s[n + 1..].iter().map(raw).collect::<String>() + rest;
+        match span {
+            Span::Plain(t) => plain(out, t, &after, closer),
+            Span::Bold(c) => decorated(out, c, '*', &after),
+            Span::Italic(c) => decorated(out, c, '/', &after),
+            Span::Strikethrough(c) => decorated(out, c, '~', &after),
+            Span::Link(text, link) => {
+                out.push('[');
+                inline(out, text, "", Some(']'));
+                out.push_str("](");
+                out.push_str(link);
+                out.push(')');
+            }
+            Span::Ref(r) => out.push_str(&r.to_string()),
+            Span::Code(code) => {
+                out.push('`');
+                out.push_str(code);
+                out.push('`');
+            }
+        }
+    }
+}
+
+/// Unescaped source text of a span, enough to tell which delimiters follow it
+fn raw(span: &Span) -> String {
+    let children = || span.children().iter().map(raw).collect::<String>();
+    match span {
+        Span::Plain(t) => t.clone(),
+        Span::Bold(_) => format!("*{}*", children()),
+        Span::Italic(_) => format!("/{}/", children()),
+        Span::Strikethrough(_) => format!("~{}~", children()),
+        Span::Link(_, link) => format!("[{}]({})", children(), link),
+        Span::Code(code) => format!("`{}`", code),
+        Span::Ref(r) => r.to_string(),
+    }
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Plain text, escaping the characters that would otherwise start markup given the text that
+/// follows on the same line, or end the enclosing decorated span early
+fn plain(out: &mut String, text: &str, rest: &str, closer: Option<char>) {
+    let chars = text.chars().collect::<Vec<_>>();
+    for (n, &c) in chars.iter().enumerate() {
+        let after = chars[n + 1..].iter().collect::<String>() + rest;
+        let escape = match c {
+            '\\' | '|' | '`' => true,
+            '[' => {
+                after.contains("](")
+                    || ((after.starts_with("[req:") || after.starts_with("[page:"))
+                        && after.contains("]]"))
+            }
+            c if Some(c) == closer => true,
+            '*' | '/' | '~' => {
+                let opens =
+                    !out.ends_with(is_word) && after.starts_with(|c: char| !c.is_whitespace());
+                opens && after.contains(c)
+            }
+            _ => false,
+        };
+        if escape {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+}
+
+fn decorated(out: &mut String, children: &[Span], delimiter: char, rest: &str) {
+    out.push(delimiter);
+    inline(
+        out,
+        children,
+        &(delimiter.to_string() + rest),
+        Some(delimiter),
+    );
+    out.push(delimiter);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::markup::without_locations;
+    use anyhow::Result;
+
+    #[test]
+    fn canonical() -> Result<()> {
+        let bp = Blueprint::parse(
+            "",
+            "#  a {#x}  [b,?c  o=x y=\"\" p=\"1, 2\"]\nd *e*\n## f\n| g |\n| h |\nx|y\n-|-\n1|2\n!include  x.bp \n<<<\n<hr>\n  >>>\n```rust\nfn a() {}\n\n```\n",
+        )?;
+        assert_eq!(
+            blueprint(&bp),
+            "# a {#x} [b,?c o=x p=\"1, 2\" y=\"\"]\n\nd *e*\n\n## f\n\n| g |\n| h |\n\nx|y\n-|-\n1|2\n\n!include x.bp\n\n<<<\n<hr>\n>>>\n\n```rust\nfn a() {}\n\n```\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn escapes() -> Result<()> {
+        for text in [
+            "read/write and ~5 ms\n",
+            "\\*a* \\/b/ x\\|y \\\\ \\[c](d)\n",
+            "*a\\*b* /c/\\/d/\n",
+            "*a /b [c ~d~](e)/* [/f/ g](h)\n",
+            "| a\\|b |\n",
+            "\\- a\n",
+            "12\\. b\n",
+            "\\* c|d\n",
+            "\\[x] e\n",
+            "- \\[ ] f\n",
+            "\\![g](h)\n",
+            "\\!include m\n",
+            "[[req:a]] [[page:#b-c]] *[[page:d.bp#e]]* \\[[req:f]]\n",
+            "![*i* \\] [j](k)](l.png)\n",
+            "`*a*` and \\` *b `c`*\n",
+        ] {
+            let bp = Blueprint::parse("", &format!("# a\n\n{}", text))?;
+            let again = Blueprint::parse("", &blueprint(&bp))?;
+            assert_eq!(
+                without_locations(&again),
+                without_locations(&bp),
+                "{}",
+                text
+            );
+        }
+        let bp = Blueprint::parse("", "# a\n\nread/write and ~5 ms\n")?;
+        assert_eq!(blueprint(&bp), "# a\n\nread/write and ~5 ms\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn front_matter() -> Result<()> {
+        let bp = Blueprint::parse(
+            "",
+            "---\nx-ref:  7\nstatus:draft\n\ntitle: T\nid:\n---\n\n# a\n",
+        )?;
+        let text = blueprint(&bp);
+        assert_eq!(
+            text,
+            "---\ntitle: T\nstatus: draft\nid:\nx-ref: 7\n---\n\n# a\n"
+        );
+        assert_eq!(
+            without_locations(&Blueprint::parse("", &text)?),
+            without_locations(&bp)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn lists() -> Result<()> {
+        let bp = Blueprint::parse("", "# a\n\n* b\n  3. c\n  7. d\n    - e\n- f\n\n1. g\n")?;
+        let text = blueprint(&bp);
+        assert_eq!(text, "# a\n\n- b\n  1. c\n  2. d\n     - e\n- f\n\n1. g\n");
+        assert_eq!(
+            without_locations(&Blueprint::parse("", &text)?),
+            without_locations(&bp)
+        );
+        let bp = Blueprint::parse("", "# a\n\n[x] b\n  2. [ ] c\n")?;
+        assert_eq!(blueprint(&bp), "# a\n\n- [x] b\n  1. [ ] c\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn round_trip() -> Result<()> {
+        let source = std::fs::read_to_string("test/sample/sample.bp")?;
+        let bp = Blueprint::parse("sample.bp", &source)?;
+        let text = blueprint(&bp);
+        let again = Blueprint::parse("sample.bp", &text)?;
+        assert_eq!(without_locations(&again), without_locations(&bp));
+        assert_eq!(blueprint(&again), text);
+
+        Ok(())
+    }
+}
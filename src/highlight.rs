@@ -0,0 +1,125 @@
+//! Server-side syntax highlighting of code blocks into the Pygments classes m.css styles inside
+//! `.m-code`
+
+use syntect::parsing::{ParseState, ScopeStack, SyntaxSet};
+
+/// m.css (Pygments) class for the innermost matching scope, most specific prefixes first
+const CLASSES: &[(&str, &str)] = &[
+    ("comment", "c"),
+    ("constant.character.escape", "se"),
+    ("string.regexp", "sr"),
+    ("string", "s"),
+    ("constant.numeric", "m"),
+    ("constant.language", "kc"),
+    ("constant.character", "sc"),
+    ("constant", "no"),
+    ("keyword.operator", "o"),
+    ("keyword", "k"),
+    ("storage.type.function", "k"),
+    ("storage.type", "kt"),
+    ("storage.modifier", "kd"),
+    ("storage", "k"),
+    ("entity.name.function", "nf"),
+    ("entity.name.type", "nc"),
+    ("entity.name.class", "nc"),
+    ("entity.name.struct", "nc"),
+    ("entity.name.enum", "nc"),
+    ("entity.name.namespace", "nn"),
+    ("entity.name.module", "nn"),
+    ("entity.name.tag", "nt"),
+    ("entity.other.attribute-name", "na"),
+    ("support.function", "nb"),
+    ("support.type", "kt"),
+    ("support.constant", "no"),
+    ("variable.parameter", "nv"),
+    ("variable.language", "bp"),
+    ("meta.preprocessor", "cp"),
+    ("markup.heading", "gh"),
+    ("markup.inserted", "gi"),
+    ("markup.deleted", "gd"),
+    ("punctuation", "p"),
+];
+
+fn syntaxes() -> &'static SyntaxSet {
+    static SYNTAXES: std::sync::OnceLock<SyntaxSet> = std::sync::OnceLock::new();
+    SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+/// Class of the text under `stack`, if any
+///
+/// Delimiters such as quotes and comment markers belong to the construct they delimit.
+fn class(stack: &ScopeStack) -> Option<&'static str> {
+    stack
+        .as_slice()
+        .iter()
+        .rev()
+        .map(|scope| scope.build_string())
+        .filter(|scope| !scope.starts_with("punctuation.definition"))
+        .find_map(|scope| {
+            CLASSES
+                .iter()
+                .find(|(prefix, _)| {
+                    scope == *prefix
+                        || (scope.starts_with(prefix) && scope[prefix.len()..].starts_with('.'))
+                })
+                .map(|(_, class)| *class)
+        })
+}
+
+/// Escaped HTML for the contents of a `<pre class="m-code">`; an unknown language is left plain
+pub fn highlight(language: &str, code: &str) -> anyhow::Result<String> {
+    let set = syntaxes();
+    let syntax = set
+        .find_syntax_by_token(language)
+        .unwrap_or_else(|| set.find_syntax_plain_text());
+    let mut state = ParseState::new(syntax);
+    let mut stack = ScopeStack::new();
+    // Runs of text sharing a class, merged so each gets a single element
+    let mut runs: Vec<(Option<&str>, String)> = vec![];
+    let mut push = |class: Option<&'static str>, text: &str| match runs.last_mut() {
+        _ if text.is_empty() => {}
+        Some((last, run)) if *last == class => run.push_str(text),
+        _ => runs.push((class, text.to_string())),
+    };
+    for line in syntect::util::LinesWithEndings::from(code) {
+        let mut pos = 0;
+        for (at, op) in state.parse_line(line, set)? {
+            push(class(&stack), &line[pos..at]);
+            pos = at;
+            stack.apply(&op)?;
+        }
+        push(class(&stack), &line[pos..]);
+    }
+    Ok(runs
+        .iter()
+        .map(|(class, text)| match class {
+            Some(class) => format!(
+                "<span class=\"{}\">{}</span>",
+                class,
+                crate::template::escape(text)
+            ),
+            None => crate::template::escape(text),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rust() -> anyhow::Result<()> {
+        let html = highlight("rust", "fn main() {\n    let s = \"<a>\"; // x\n}\n")?;
+        assert!(html.contains("<span class=\"k\">fn</span>"), "{}", html);
+        assert!(html.contains("<span class=\"nf\">main</span>"), "{}", html);
+        assert!(
+            html.contains("<span class=\"s\">&quot;&lt;a&gt;&quot;</span>"),
+            "{}",
+            html
+        );
+        assert!(html.contains("<span class=\"c\">// x"), "{}", html);
+        assert_eq!(highlight("nonsense", "a < b\n")?, "a &lt; b\n");
+
+        Ok(())
+    }
+}
@@ -1,53 +1,422 @@
+mod config;
+mod diagnostic;
+mod format;
+mod highlight;
 mod markup;
 mod model;
 mod resource;
+mod serve;
+mod source;
 mod template;
+mod trace;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 
 /// Command line program options
 #[derive(clap_derive::Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 pub struct ProgramOptions {
-    /// Path to Doxygen xml output
-    #[clap(value_parser)]
-    input_path: String,
+    /// Project configuration file
+    #[clap(long, global = true, value_parser)]
+    config: Option<String>,
+    /// Only print errors
+    #[clap(long, short, global = true, value_parser)]
+    quiet: bool,
+    /// Embedded theme name or theme directory to render with, overriding the configuration
+    #[clap(long, global = true, value_parser)]
+    theme: Option<String>,
+    /// Bundle fonts and fail if the output references any external resource
+    #[clap(long, global = true, value_parser)]
+    offline: bool,
+    /// Directory of templates replacing the built-in ones of the same name
+    #[clap(long, global = true, value_parser)]
+    templates: Option<String>,
+    #[clap(subcommand)]
+    command: Command,
+}
+
+/// Where to find blueprint sources
+#[derive(clap_derive::Args, Debug)]
+pub struct SourceOptions {
+    /// Directory containing the blueprint sources, searched recursively [default: from
+    /// blueprint.toml]
     #[clap(value_parser)]
-    output_path: String,
+    input_path: Option<String>,
+    /// Only use files matching this glob, relative to the input directory [default: *.bp]
+    #[clap(long, value_parser)]
+    include: Vec<String>,
+    /// Skip files and directories matching this glob, relative to the input directory
+    #[clap(long, value_parser)]
+    exclude: Vec<String>,
 }
 
-fn process(options: ProgramOptions) -> Result<()> {
-    let input_path = std::path::Path::new(&options.input_path);
-    anyhow::ensure!(input_path.exists());
-    let output_path = std::path::Path::new(&options.output_path);
+fn finder(config: &config::Config) -> Result<source::Finder> {
+    let root = config
+        .input
+        .path
+        .as_ref()
+        .context("no input directory given (pass one or set input.path in blueprint.toml)")?;
+    source::Finder::new(root, &config.input.include, &config.input.exclude)
+}
+
+/// Blueprint sources to load and how strictly to validate them
+#[derive(clap_derive::Args, Debug)]
+pub struct InputOptions {
+    #[clap(flatten)]
+    sources: SourceOptions,
+    /// Fail when the model has any warnings
+    #[clap(long, value_parser)]
+    deny_warnings: bool,
+}
+
+#[derive(clap_derive::Subcommand, Debug)]
+pub enum Command {
+    /// Render blueprints to an HTML site
+    Build {
+        #[clap(flatten)]
+        input: InputOptions,
+        /// Directory to write the generated site to [default: from blueprint.toml]
+        #[clap(value_parser)]
+        output_path: Option<String>,
+    },
+    /// Parse and validate blueprints without writing any output
+    Check {
+        #[clap(flatten)]
+        input: InputOptions,
+    },
+    /// Rewrite blueprint sources in canonical form
+    Fmt {
+        #[clap(flatten)]
+        sources: SourceOptions,
+        /// Only report files that are not formatted, without changing them
+        #[clap(long, value_parser)]
+        check: bool,
+    },
+    /// Write the parsed model as JSON
+    Export {
+        #[clap(flatten)]
+        input: InputOptions,
+        /// File to write to instead of standard output
+        #[clap(long, short, value_parser)]
+        output: Option<String>,
+    },
+    /// List requirements
+    Query {
+        #[clap(flatten)]
+        input: InputOptions,
+        /// Requirement names to show (all if omitted)
+        #[clap(value_parser)]
+        names: Vec<String>,
+        /// Only show requirements nothing satisfies
+        #[clap(long, value_parser)]
+        unsatisfied: bool,
+        /// Only show requirements declared in this page
+        #[clap(long, value_parser)]
+        page: Option<String>,
+        /// Print JSON instead of one line per requirement
+        #[clap(long, value_parser)]
+        json: bool,
+    },
+    /// Build to a directory and serve it, rebuilding when sources change
+    Serve {
+        #[clap(flatten)]
+        input: InputOptions,
+        /// Address to listen on
+        #[clap(long, value_parser, default_value = "127.0.0.1:8000")]
+        address: String,
+        /// Directory to build into (from blueprint.toml, else a temporary directory)
+        #[clap(long, value_parser)]
+        output_path: Option<String>,
+    },
+}
+
+impl Command {
+    fn sources(&self) -> &SourceOptions {
+        match self {
+            Command::Build { input, .. }
+            | Command::Check { input }
+            | Command::Export { input, .. }
+            | Command::Query { input, .. }
+            | Command::Serve { input, .. } => &input.sources,
+            Command::Fmt { sources, .. } => sources,
+        }
+    }
+}
+
+/// Find the project configuration and apply command line overrides to it
+fn configure(options: &ProgramOptions) -> Result<config::Config> {
+    let sources = options.command.sources();
+    let mut config = config::Config::discover(
+        options.config.as_deref().map(std::path::Path::new),
+        sources.input_path.as_deref().map(std::path::Path::new),
+    )?;
+    if let Some(path) = &sources.input_path {
+        config.input.path = Some(path.into());
+    }
+    if !sources.include.is_empty() {
+        config.input.include = sources.include.clone();
+    }
+    if !sources.exclude.is_empty() {
+        config.input.exclude = sources.exclude.clone();
+    }
+    if let Some(theme) = &options.theme {
+        if std::path::Path::new(theme).is_dir() {
+            config.theme.path = Some(theme.into());
+        } else {
+            config.theme.name = theme.clone();
+            config.theme.path = None;
+        }
+    }
+    if options.offline {
+        config.output.offline = true;
+    }
+    if let Some(templates) = &options.templates {
+        config.templates.path = Some(templates.into());
+    }
+    if let Command::Build {
+        output_path: Some(path),
+        ..
+    }
+    | Command::Serve {
+        output_path: Some(path),
+        ..
+    } = &options.command
+    {
+        config.output.path = Some(path.into());
+    }
+    Ok(config)
+}
+
+/// Parse and validate every blueprint, printing warnings unless quiet
+fn load(
+    options: &ProgramOptions,
+    config: &config::Config,
+    input: &InputOptions,
+) -> Result<model::Model> {
+    let finder = finder(config)?;
+    let (blueprints, errors) = markup::Blueprint::load_pages(finder.root(), &finder.find()?);
+    let mut errors = diagnostic::Diagnostics(errors);
+
+    let model = model::Model::new(config.clone(), blueprints);
+    let (denied, warnings): (Vec<_>, Vec<_>) = model
+        .diagnostics()
+        .into_iter()
+        .partition(|d| d.severity == diagnostic::Severity::Error);
+    if !options.quiet {
+        for warning in &warnings {
+            eprintln!("{}\n", warning);
+        }
+    }
+    errors.0.extend(denied);
+    if !errors.0.is_empty() {
+        return Err(errors.into());
+    }
+    anyhow::ensure!(
+        !input.deny_warnings || warnings.is_empty(),
+        "{} warning(s) denied by --deny-warnings",
+        warnings.len()
+    );
+    Ok(model)
+}
+
+fn build(options: &ProgramOptions, config: &config::Config, input: &InputOptions) -> Result<()> {
+    let output_path = config
+        .output
+        .path
+        .as_ref()
+        .context("no output directory given (pass one or set output.path in blueprint.toml)")?;
+    let model = load(options, config, input)?;
     std::fs::create_dir_all(output_path).ok();
-    anyhow::ensure!(!output_path.is_file());
-    let blueprints = input_path
-        .read_dir()?
-        .filter_map(|entry| -> Option<markup::Blueprint> {
-            match entry.ok()? {
-                e if e.file_type().ok()?.is_file() => {
-                    let bp = markup::Blueprint::parse_file(&e.path());
-                    if let Err(er) = &bp {
-                        println!("{}", er)
-                    }
-                    bp.ok()
-                }
-                e if e.file_type().ok()?.is_dir() => None,
-                _ => unreachable!(),
+    anyhow::ensure!(
+        output_path.is_dir(),
+        "output path {} is not a directory",
+        output_path.display()
+    );
+    if !config.output.offline {
+        return model
+            .store(output_path)
+            .with_context(|| format!("writing {}", output_path.display()));
+    }
+    // Checked while staged, so output referencing external resources is never written
+    let staging = tempfile::tempdir().context("creating a staging directory")?;
+    model
+        .store(staging.path())
+        .with_context(|| format!("writing {}", output_path.display()))?;
+    let urls = resource::external_urls(staging.path())?;
+    anyhow::ensure!(
+        urls.is_empty(),
+        "offline output references external resources:\n  {}",
+        urls.join("\n  ")
+    );
+    resource::copy_dir(staging.path(), output_path, &[])
+        .with_context(|| format!("writing {}", output_path.display()))
+}
+
+fn fmt(options: &ProgramOptions, config: &config::Config, check: bool) -> Result<()> {
+    let mut errors = diagnostic::Diagnostics::default();
+    let mut unformatted = vec![];
+    let finder = finder(config)?;
+    for file in finder.find()? {
+        let source = std::fs::read_to_string(&file)
+            .with_context(|| format!("reading {}", file.display()))?;
+        let bp = match markup::Blueprint::parse_source(finder.root(), &file, &source) {
+            Ok(bp) => bp,
+            Err(err) => {
+                errors
+                    .0
+                    .extend(err.downcast::<diagnostic::Diagnostics>()?.0);
+                continue;
             }
-        });
-    let _eng = template::Engine::new()?;
+        };
+        let text = format::blueprint(&bp);
+        if text == source {
+            continue;
+        }
+        if check {
+            unformatted.push(file.display().to_string());
+        } else {
+            std::fs::write(&file, text).with_context(|| format!("writing {}", file.display()))?;
+            if !options.quiet {
+                eprintln!("formatted {}", file.display());
+            }
+        }
+    }
+    if !errors.0.is_empty() {
+        return Err(errors.into());
+    }
+    anyhow::ensure!(
+        unformatted.is_empty(),
+        "not formatted: {}",
+        unformatted.join(", ")
+    );
+    Ok(())
+}
+
+fn export(
+    options: &ProgramOptions,
+    config: &config::Config,
+    input: &InputOptions,
+    output: &Option<String>,
+) -> Result<()> {
+    let json = serde_json::to_string_pretty(&load(options, config, input)?.export())?;
+    match output {
+        Some(file) => {
+            std::fs::write(file, json + "\n").with_context(|| format!("writing {}", file))
+        }
+        None => {
+            println!("{}", json);
+            Ok(())
+        }
+    }
+}
 
-    model::Model::new(blueprints).store(output_path)?;
+fn query(
+    options: &ProgramOptions,
+    config: &config::Config,
+    input: &InputOptions,
+    names: &[String],
+    unsatisfied: bool,
+    page: &Option<String>,
+    json: bool,
+) -> Result<()> {
+    let found = load(options, config, input)?
+        .requirement_index()
+        .into_iter()
+        .filter(|r| names.is_empty() || names.contains(&r.name))
+        .filter(|r| !unsatisfied || !r.satisfied)
+        .filter(|r| page.as_ref().is_none_or(|p| &r.page == p))
+        .collect::<Vec<_>>();
+    if json {
+        println!("{}", serde_json::to_string_pretty(&found)?);
+    } else {
+        for r in &found {
+            let state = if r.satisfied {
+                "satisfied"
+            } else {
+                "unsatisfied"
+            };
+            println!("{}\t{}\t{}\t{}", r.name, state, r.page, r.title);
+        }
+    }
+    let missing = names
+        .iter()
+        .filter(|n| !found.iter().any(|r| &r.name == *n))
+        .cloned()
+        .collect::<Vec<_>>();
+    anyhow::ensure!(
+        unsatisfied || page.is_some() || missing.is_empty(),
+        "unknown requirement(s): {}",
+        missing.join(", ")
+    );
     Ok(())
 }
 
-fn main() {
+fn serve(
+    options: &ProgramOptions,
+    config: &config::Config,
+    input: &InputOptions,
+    address: &str,
+) -> Result<()> {
+    let mut config = config.clone();
+    // Held for as long as the server runs, and removed with it
+    let scratch = tempfile::Builder::new()
+        .prefix("blueprint-serve-")
+        .tempdir()
+        .context("creating an output directory")?;
+    let output_path = config
+        .output
+        .path
+        .get_or_insert_with(|| scratch.path().to_path_buf())
+        .clone();
+    let mut built: Option<std::time::SystemTime> = None;
+    let mut refresh = || -> Result<()> {
+        let modified = finder(&config)?
+            .find()?
+            .iter()
+            .filter_map(|f| f.metadata().and_then(|m| m.modified()).ok())
+            .max();
+        if built.is_none() || modified > built {
+            build(options, &config, input)?;
+            built = modified.or(Some(std::time::SystemTime::UNIX_EPOCH));
+        }
+        Ok(())
+    };
+    refresh()?;
+    if !options.quiet {
+        eprintln!("serving {} on http://{}/", output_path.display(), address);
+    }
+    serve::serve(address, &output_path, refresh)
+}
+
+fn process(options: ProgramOptions) -> Result<()> {
+    let config = configure(&options)?;
+    match &options.command {
+        Command::Build { input, .. } => build(&options, &config, input),
+        Command::Check { input } => load(&options, &config, input).map(|_| ()),
+        Command::Fmt { check, .. } => fmt(&options, &config, *check),
+        Command::Export { input, output } => export(&options, &config, input, output),
+        Command::Query {
+            input,
+            names,
+            unsatisfied,
+            page,
+            json,
+        } => query(&options, &config, input, names, *unsatisfied, page, *json),
+        Command::Serve { input, address, .. } => serve(&options, &config, input, address),
+    }
+}
+
+fn main() -> std::process::ExitCode {
     use clap::StructOpt;
 
     let options = ProgramOptions::parse();
-    process(options).ok();
+    match process(options) {
+        Ok(()) => std::process::ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{:#}", err);
+            std::process::ExitCode::FAILURE
+        }
+    }
 }
 
 #[cfg(test)]
@@ -57,16 +426,228 @@ mod tests {
     const INPUT_PATH: &str = "test/sample";
     const OUTPUT_PATH: &str = "target/out";
 
+    fn make_input(name: &str, files: &[(&str, &str)]) -> tempfile::TempDir {
+        let dir = tempfile::Builder::new()
+            .prefix(&format!("blueprint-{}-", name))
+            .tempdir()
+            .unwrap();
+        for (file, text) in files {
+            let path = dir.path().join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, text).unwrap();
+        }
+        dir
+    }
+
+    fn run(args: &[&str]) -> anyhow::Result<()> {
+        super::process(super::ProgramOptions::try_parse_from(
+            ["blueprint", "--quiet"].iter().chain(args),
+        )?)
+    }
+
     #[test]
     #[ignore]
     fn generate() {
-        let options = super::ProgramOptions::parse_from(["blueprint", INPUT_PATH, OUTPUT_PATH]);
-        super::process(options).unwrap();
+        run(&["build", INPUT_PATH, OUTPUT_PATH]).unwrap();
     }
 
     #[test]
     fn options() {
-        let options = super::ProgramOptions::parse_from(["blueprint", INPUT_PATH, OUTPUT_PATH]);
-        assert_eq!(options.input_path, INPUT_PATH);
+        let options =
+            super::ProgramOptions::parse_from(["blueprint", "build", INPUT_PATH, OUTPUT_PATH]);
+        assert!(
+            matches!(options.command, super::Command::Build { input, .. } if input.sources.input_path.as_deref() == Some(INPUT_PATH))
+        );
+        let options =
+            super::ProgramOptions::parse_from(["blueprint", "check", INPUT_PATH, "--quiet"]);
+        assert!(options.quiet);
+        assert!(matches!(options.command, super::Command::Check { .. }));
+    }
+
+    #[test]
+    fn check() {
+        run(&["check", "--deny-warnings", INPUT_PATH]).unwrap();
+    }
+
+    #[test]
+    fn errors() {
+        assert!(run(&["check", "test/missing"]).is_err());
+        assert!(run(&["--config", "test/missing.toml", "check", INPUT_PATH]).is_err());
+
+        let dir = make_input("errors", &[("a.bp", "# a\n### b\n")]);
+        assert!(run(&["check", dir.path().to_str().unwrap()]).is_err());
+
+        let dir = make_input("warnings", &[("a.bp", "# a [?r]\n")]);
+        assert!(run(&["check", dir.path().to_str().unwrap()]).is_ok());
+        assert!(run(&["check", "--deny-warnings", dir.path().to_str().unwrap()]).is_err());
+    }
+
+    #[test]
+    fn fmt() {
+        let dir = make_input("fmt", &[("a.bp", "#  a [b]\ntext\n")]);
+        assert!(run(&["fmt", "--check", dir.path().to_str().unwrap()]).is_err());
+        run(&["fmt", dir.path().to_str().unwrap()]).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("a.bp")).unwrap(),
+            "# a [b]\n\ntext\n"
+        );
+        run(&["fmt", "--check", dir.path().to_str().unwrap()]).unwrap();
+    }
+
+    #[test]
+    fn export() {
+        let dir = make_input("export", &[]);
+        let file = dir.path().join("model.json");
+        run(&["export", INPUT_PATH, "-o", file.to_str().unwrap()]).unwrap();
+        let export: crate::model::Export =
+            serde_json::from_str(&std::fs::read_to_string(file).unwrap()).unwrap();
+        assert_eq!(export.pages.len(), 1);
+        assert_eq!(export.requirements[0].name, "tag1");
+    }
+
+    #[test]
+    fn nested() {
+        let dir = make_input(
+            "nested",
+            &[
+                ("net/api.bp", "# Net"),
+                ("ui/api.bp", "# UI"),
+                ("ui/readme.txt", "ignored"),
+            ],
+        );
+        let out = dir.path().join("out");
+        run(&[
+            "build",
+            dir.path().to_str().unwrap(),
+            out.to_str().unwrap(),
+            "--exclude",
+            "out",
+        ])
+        .unwrap();
+        assert!(out.join("bp_net__api.bp.html").is_file());
+        assert!(out.join("bp_ui__api.bp.html").is_file());
+    }
+
+    #[test]
+    fn include() {
+        let dir = make_input(
+            "include",
+            &[
+                ("main.bp", "# Main\n\n!include parts/a.bp\n"),
+                ("parts/a.bp", "# A [?r]\n"),
+            ],
+        );
+        let file = dir.path().join("model.json");
+        run(&[
+            "export",
+            dir.path().to_str().unwrap(),
+            "-o",
+            file.to_str().unwrap(),
+        ])
+        .unwrap();
+        let export: crate::model::Export =
+            serde_json::from_str(&std::fs::read_to_string(file).unwrap()).unwrap();
+        assert_eq!(export.pages.len(), 1);
+        assert_eq!(export.pages[0].includes, vec!["parts/a.bp"]);
+        assert_eq!(export.requirements[0].page, "main.bp");
+        assert_eq!(export.diagnostics[0].file, "parts/a.bp");
+    }
+
+    #[test]
+    fn include_errors() {
+        let dir = make_input(
+            "include-errors",
+            &[
+                ("main.bp", "# Main\n\n!include parts/a.bp\n"),
+                ("parts/a.bp", "# A\n### B\n"),
+                ("x.bp", "# X\n\n!include y.bp\n"),
+                ("y.bp", "# Y\n\n!include x.bp\n"),
+            ],
+        );
+        let err = run(&["check", dir.path().to_str().unwrap()]).unwrap_err();
+        let diags = err.downcast::<crate::diagnostic::Diagnostics>().unwrap().0;
+        assert_eq!(
+            diags
+                .iter()
+                .map(|d| {
+                    let file = d.file.rsplit('/').next().unwrap();
+                    (file, d.message.split(':').next().unwrap())
+                })
+                .collect::<Vec<_>>(),
+            vec![("a.bp", "wrong rank"), ("y.bp", "include cycle")],
+            "{:?}",
+            diags
+        );
+    }
+
+    #[test]
+    fn query() {
+        run(&["query", INPUT_PATH, "tag1"]).unwrap();
+        assert!(run(&["query", INPUT_PATH, "nope"]).is_err());
+    }
+
+    #[test]
+    fn config() {
+        let dir = make_input(
+            "config",
+            &[
+                (
+                    "blueprint.toml",
+                    "[output]\npath = \"site\"\npage_prefix = \"page-\"\n\n[lint]\nunsatisfied = \"deny\"\n",
+                ),
+                ("docs/a.bp", "# A [=r]"),
+            ],
+        );
+        run(&["build", dir.path().to_str().unwrap(), "--exclude", "site"]).unwrap();
+        assert!(dir.path().join("site/page-docs__a.bp.html").is_file());
+
+        std::fs::write(dir.path().join("docs/b.bp"), "# B [?q]").unwrap();
+        assert!(run(&["check", dir.path().to_str().unwrap(), "--exclude", "site"]).is_err());
+        let config = dir.path().join("blueprint.toml");
+        std::fs::write(&config, "[input]\npath = \"docs\"\n").unwrap();
+        run(&["--config", config.to_str().unwrap(), "check"]).unwrap();
+        let out = dir.path().join("out");
+        assert!(run(&[
+            "--theme",
+            "nope",
+            "build",
+            dir.path().to_str().unwrap(),
+            out.to_str().unwrap()
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn offline() {
+        let dir = make_input("offline", &[]);
+        run(&[
+            "--offline",
+            "build",
+            INPUT_PATH,
+            dir.path().to_str().unwrap(),
+        ])
+        .unwrap();
+        let page = std::fs::read_to_string(dir.path().join("pages.html")).unwrap();
+        assert!(page.contains("theme/fonts.css"));
+        assert!(!page.contains("googleapis"));
+
+        let templates = make_input(
+            "offline-templates",
+            &[(
+                "page_head.html",
+                "<script src=\"https://cdn.example/x.js\"></script>",
+            )],
+        );
+        let out = dir.path().join("rejected");
+        assert!(run(&[
+            "--offline",
+            "--templates",
+            templates.path().to_str().unwrap(),
+            "build",
+            INPUT_PATH,
+            out.to_str().unwrap()
+        ])
+        .is_err());
+        assert!(!out.join("pages.html").exists());
     }
 }
@@ -1,46 +1,198 @@
 use anyhow::{Context, Result};
 
+/// Byte range and 1-based line/column of a node in its source file
+#[derive(
+    Clone,
+    Copy,
+    Default,
+    Debug,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+pub struct Location {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Location {
+    fn new(start: usize, end: usize) -> Location {
+        Location {
+            start,
+            end,
+            ..Default::default()
+        }
+    }
+}
+
+/// A tree as JSON with every location removed, so trees parsed from different sources can be
+/// compared structurally
+#[cfg(test)]
+pub fn without_locations<T: serde::Serialize>(tree: &T) -> serde_json::Value {
+    fn strip(value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::Object(map) => {
+                map.remove("location");
+                map.values_mut().for_each(strip);
+            }
+            serde_json::Value::Array(items) => items.iter_mut().for_each(strip),
+            _ => {}
+        }
+    }
+
+    let mut value = serde_json::to_value(tree).unwrap();
+    strip(&mut value);
+    value
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// An enum node together with its location
 #[derive(Clone, Default, PartialEq, Debug, serde::Serialize, serde::Deserialize)]
-pub enum TagCategory {
-    #[default]
-    Simple,
-    Requires,
-    Satisfies,
+pub struct Located<T> {
+    #[serde(flatten)]
+    pub node: T,
+    pub location: Location,
+}
+
+impl<T> From<T> for Located<T> {
+    fn from(node: T) -> Located<T> {
+        Located {
+            node,
+            location: Default::default(),
+        }
+    }
+}
+
+impl<T> std::ops::Deref for Located<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.node
+    }
 }
 
+/// Offsets of every line start, used to turn byte offsets into line/column pairs
+struct LineIndex<'a> {
+    input: &'a str,
+    starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    fn new(input: &'a str) -> LineIndex<'a> {
+        LineIndex {
+            input,
+            starts: std::iter::once(0)
+                .chain(input.match_indices('\n').map(|(i, _)| i + 1))
+                .collect(),
+        }
+    }
+
+    fn resolve(&self, location: &mut Location) {
+        let line = self.starts.partition_point(|&s| s <= location.start);
+        let start = self.starts[line - 1];
+        location.line = line;
+        location.column = self.input[start..location.start].chars().count() + 1;
+    }
+}
+
+/// Fills in line/column information once the byte ranges are known
+trait Locate {
+    fn locate(&mut self, index: &LineIndex);
+}
+
+impl<T: Locate> Locate for Vec<T> {
+    fn locate(&mut self, index: &LineIndex) {
+        self.iter_mut().for_each(|n| n.locate(index));
+    }
+}
+
+impl<T: Locate> Locate for Located<T> {
+    fn locate(&mut self, index: &LineIndex) {
+        index.resolve(&mut self.location);
+        self.node.locate(index);
+    }
+}
+
+/// Characters that may open a tag to give it a meaning; the configuration decides which
+pub const SIGILS: &str = "?=!~^<>+&@%$";
+
 #[derive(Clone, Default, PartialEq, Debug, serde::Serialize, serde::Deserialize)]
 pub struct Tag {
-    pub category: TagCategory,
+    /// One of [`SIGILS`], absent for a simple tag
+    pub sigil: Option<char>,
     pub name: String,
+    /// `key=value` pairs following the name
+    pub attributes: std::collections::BTreeMap<String, String>,
+    /// Keys given again after their first value, which is the one kept, with where each repeat is
+    #[serde(skip)]
+    pub repeated: Vec<(String, Location)>,
+    pub location: Location,
 }
 
-impl Tag {
-    pub fn file_name(&self) -> String {
-        let prefix = match self.category {
-            TagCategory::Simple => "tag_".to_string(),
-            TagCategory::Requires => "req_".to_string(),
-            TagCategory::Satisfies => "req_".to_string(),
-        };
-        prefix + &self.name + ".html"
+impl Locate for Tag {
+    fn locate(&mut self, index: &LineIndex) {
+        index.resolve(&mut self.location);
+        self.repeated
+            .iter_mut()
+            .for_each(|(_, location)| index.resolve(location));
     }
 }
 
 #[derive(Clone, Default, PartialEq, Debug, serde::Serialize, serde::Deserialize)]
-pub enum SpanType {
+pub enum RefKind {
     #[default]
-    Raw,
-    Bold,
-    Italic,
-    Strikethrough,
+    Requirement,
+    Page,
+}
+
+/// An inline `[[req:name]]` or `[[page:name#section]]` cross-reference
+#[derive(Clone, Default, PartialEq, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Reference {
+    pub kind: RefKind,
+    /// Requirement or page name; an empty page name refers to the current page
+    pub target: String,
+    /// Anchor of a section within the page
+    pub section: Option<String>,
+    /// Title and link of the target, filled in by the model
+    pub title: Option<String>,
+    pub link: Option<String>,
+}
+
+impl std::fmt::Display for Reference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = match self.kind {
+            RefKind::Requirement => "req",
+            RefKind::Page => "page",
+        };
+        write!(f, "[[{}:{}", kind, self.target)?;
+        if let Some(section) = &self.section {
+            write!(f, "#{}", section)?;
+        }
+        write!(f, "]]")
+    }
 }
 
 #[derive(Clone, PartialEq, Debug, serde::Serialize, serde::Deserialize)]
 pub enum Span {
     Plain(String),
-    Bold(String),
-    Italic(String),
-    Strikethrough(String),
-    Link((String, String)),
+    Bold(Vec<Span>),
+    Italic(Vec<Span>),
+    Strikethrough(Vec<Span>),
+    /// Link text and URL
+    Link(Vec<Span>, String),
+    /// Text between backticks, taken verbatim
+    Code(String),
+    Ref(Reference),
 }
 
 impl Default for Span {
@@ -49,31 +201,261 @@ impl Default for Span {
     }
 }
 
+impl Locate for Span {
+    fn locate(&mut self, _index: &LineIndex) {}
+}
+
 impl Span {
-    fn from_decoration(dec: char, text: String) -> Span {
+    fn from_decoration(dec: char, children: Vec<Span>) -> Span {
         match dec {
-            '*' => Span::Bold(text),
-            '/' => Span::Italic(text),
-            '~' => Span::Strikethrough(text),
+            '*' => Span::Bold(children),
+            '/' => Span::Italic(children),
+            '~' => Span::Strikethrough(children),
             _ => unreachable!(),
         }
     }
+
+    /// Spans nested directly inside this one
+    pub fn children(&self) -> &[Span] {
+        match self {
+            Span::Plain(_) | Span::Code(_) | Span::Ref(_) => &[],
+            Span::Bold(c) | Span::Italic(c) | Span::Strikethrough(c) | Span::Link(c, _) => c,
+        }
+    }
+
+    fn children_mut(&mut self) -> &mut [Span] {
+        match self {
+            Span::Plain(_) | Span::Code(_) | Span::Ref(_) => &mut [],
+            Span::Bold(c) | Span::Italic(c) | Span::Strikethrough(c) | Span::Link(c, _) => c,
+        }
+    }
+
+    /// The text of the span with all formatting removed
+    pub fn text(&self) -> String {
+        match self {
+            Span::Plain(t) | Span::Code(t) => t.clone(),
+            Span::Ref(r) => r.title.clone().unwrap_or_else(|| r.to_string()),
+            _ => self.children().iter().map(Span::text).collect(),
+        }
+    }
+
+    /// Call `f` on this span and every span nested inside it, parents first
+    pub fn visit<F: FnMut(&Span)>(&self, f: &mut F) {
+        f(self);
+        for child in self.children() {
+            child.visit(f);
+        }
+    }
+
+    /// Like [`Span::visit`], allowing `f` to change the spans
+    pub fn visit_mut<F: FnMut(&mut Span)>(&mut self, f: &mut F) {
+        f(self);
+        for child in self.children_mut() {
+            child.visit_mut(f);
+        }
+    }
+}
+
+impl From<&str> for Span {
+    fn from(text: &str) -> Span {
+        Span::Plain(text.to_string())
+    }
 }
 
 #[derive(Clone, Default, PartialEq, Debug, serde::Serialize, serde::Deserialize)]
 pub struct Table {
-    pub heading: Vec<Vec<Span>>,
-    pub body: Vec<Vec<Vec<Span>>>,
+    pub heading: Vec<Vec<Located<Span>>>,
+    pub body: Vec<Vec<Vec<Located<Span>>>>,
+}
+
+impl Locate for Table {
+    fn locate(&mut self, index: &LineIndex) {
+        self.heading.locate(index);
+        self.body.locate(index);
+    }
+}
+
+#[derive(Clone, Default, PartialEq, Debug, serde::Serialize, serde::Deserialize)]
+pub struct ListItem {
+    pub spans: Vec<Located<Span>>,
+    /// Whether a `[ ]`/`[x]` task item is done
+    pub task: Option<bool>,
+    /// A list indented below the item
+    pub list: Option<List>,
+}
+
+impl Locate for ListItem {
+    fn locate(&mut self, index: &LineIndex) {
+        self.spans.locate(index);
+        if let Some(list) = &mut self.list {
+            list.locate(index);
+        }
+    }
+}
+
+/// A `1.` numbered or `-`/`*` bulleted list
+#[derive(Clone, Default, PartialEq, Debug, serde::Serialize, serde::Deserialize)]
+pub struct List {
+    pub ordered: bool,
+    pub items: Vec<ListItem>,
+}
+
+impl Locate for List {
+    fn locate(&mut self, index: &LineIndex) {
+        self.items.locate(index);
+    }
+}
+
+impl List {
+    /// Every span in the list and the lists nested in it, in source order
+    pub fn spans(&self) -> Vec<&Located<Span>> {
+        self.items
+            .iter()
+            .flat_map(|item| {
+                item.spans
+                    .iter()
+                    .chain(item.list.iter().flat_map(List::spans))
+            })
+            .collect()
+    }
+
+    pub fn spans_mut(&mut self) -> Vec<&mut Located<Span>> {
+        self.items
+            .iter_mut()
+            .flat_map(|item| {
+                item.spans
+                    .iter_mut()
+                    .chain(item.list.iter_mut().flat_map(List::spans_mut))
+            })
+            .collect()
+    }
+
+    /// Tasks in the list and the lists nested in it
+    pub fn progress(&self) -> Progress {
+        self.items
+            .iter()
+            .map(|item| {
+                let own = match item.task {
+                    Some(done) => Progress {
+                        done: done as usize,
+                        total: 1,
+                    },
+                    None => Progress::default(),
+                };
+                own + item.list.as_ref().map(List::progress).unwrap_or_default()
+            })
+            .sum()
+    }
+}
+
+/// An image with a caption, written `![caption](path)`
+#[derive(Clone, Default, PartialEq, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Figure {
+    pub caption: Vec<Located<Span>>,
+    /// Image file relative to the blueprint, or the output file once the model has copied it
+    pub path: String,
+    /// Position among the figures of the page it is rendered on, starting at 1
+    pub number: usize,
+}
+
+impl Locate for Figure {
+    fn locate(&mut self, index: &LineIndex) {
+        self.caption.locate(index);
+    }
+}
+
+/// Completed and total task items
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Progress {
+    pub done: usize,
+    pub total: usize,
+}
+
+impl std::ops::Add for Progress {
+    type Output = Progress;
+
+    fn add(self, other: Progress) -> Progress {
+        Progress {
+            done: self.done + other.done,
+            total: self.total + other.total,
+        }
+    }
+}
+
+impl std::iter::Sum for Progress {
+    fn sum<I: Iterator<Item = Progress>>(iter: I) -> Progress {
+        iter.fold(Progress::default(), std::ops::Add::add)
+    }
 }
 
 #[derive(Clone, Default, PartialEq, Debug, serde::Serialize, serde::Deserialize)]
 pub enum Paragraph {
     #[default]
     Empty,
-    Spans(Vec<Span>),
-    Block(Vec<Span>),
-    Note(Vec<Span>),
+    Spans(Vec<Located<Span>>),
+    Block(Vec<Located<Span>>),
+    Note(Vec<Located<Span>>),
     Table(Table),
+    List(List),
+    Figure(Figure),
+    /// An `!include` directive, replaced by the included sections when the blueprint is loaded
+    Include(String),
+    /// Lines between `<<<` and `>>>`, passed through to the output unescaped
+    Html(String),
+    /// Language tag and the lines between the ``` fences
+    Code(String, String),
+}
+
+impl Paragraph {
+    /// Every span in the paragraph, in source order
+    pub fn spans(&self) -> Vec<&Located<Span>> {
+        match self {
+            Paragraph::Empty | Paragraph::Html(_) | Paragraph::Code(..) | Paragraph::Include(_) => {
+                vec![]
+            }
+            Paragraph::Spans(s) | Paragraph::Block(s) | Paragraph::Note(s) => s.iter().collect(),
+            Paragraph::Table(t) => t
+                .heading
+                .iter()
+                .chain(t.body.iter().flatten())
+                .flatten()
+                .collect(),
+            Paragraph::List(l) => l.spans(),
+            Paragraph::Figure(f) => f.caption.iter().collect(),
+        }
+    }
+
+    pub fn spans_mut(&mut self) -> Vec<&mut Located<Span>> {
+        match self {
+            Paragraph::Empty | Paragraph::Html(_) | Paragraph::Code(..) | Paragraph::Include(_) => {
+                vec![]
+            }
+            Paragraph::Spans(s) | Paragraph::Block(s) | Paragraph::Note(s) => {
+                s.iter_mut().collect()
+            }
+            Paragraph::Table(t) => t
+                .heading
+                .iter_mut()
+                .chain(t.body.iter_mut().flatten())
+                .flatten()
+                .collect(),
+            Paragraph::List(l) => l.spans_mut(),
+            Paragraph::Figure(f) => f.caption.iter_mut().collect(),
+        }
+    }
+}
+
+impl Locate for Paragraph {
+    fn locate(&mut self, index: &LineIndex) {
+        match self {
+            Paragraph::Empty | Paragraph::Html(_) | Paragraph::Code(..) | Paragraph::Include(_) => {
+            }
+            Paragraph::Spans(s) | Paragraph::Block(s) | Paragraph::Note(s) => s.locate(index),
+            Paragraph::Table(t) => t.locate(index),
+            Paragraph::List(l) => l.locate(index),
+            Paragraph::Figure(f) => f.locate(index),
+        }
+    }
 }
 
 #[derive(Clone, Default, PartialEq, Debug, serde::Serialize, serde::Deserialize)]
@@ -81,23 +463,62 @@ pub struct Heading {
     pub rank: usize,
     pub tags: Vec<Tag>,
     pub text: String,
+    /// Explicit `{#id}` anchor
+    pub id: Option<String>,
+    /// Fragment naming the heading within its page: its id, else the [`slug`] of its text, made
+    /// unique by a numeric suffix
+    pub anchor: String,
+    pub location: Location,
+}
+
+impl Locate for Heading {
+    fn locate(&mut self, index: &LineIndex) {
+        index.resolve(&mut self.location);
+        self.tags.locate(index);
+    }
 }
 
 #[derive(Clone, Default, PartialEq, Debug, serde::Serialize, serde::Deserialize)]
 pub struct Section {
     pub heading: Heading,
-    pub body: Vec<Paragraph>,
+    pub body: Vec<Located<Paragraph>>,
     pub subsections: Vec<Section>,
+    pub location: Location,
+    /// Name of the blueprint an included section was read from; its locations point into that
+    /// file rather than the page's own
+    pub source: Option<String>,
+}
+
+impl Locate for Section {
+    fn locate(&mut self, index: &LineIndex) {
+        index.resolve(&mut self.location);
+        self.heading.locate(index);
+        self.body.locate(index);
+        self.subsections.locate(index);
+    }
 }
 
 impl Section {
-    pub fn find_tags(&self, category: TagCategory) -> Vec<&Tag> {
+    pub fn find_tags(&self, sigil: Option<char>) -> Vec<&Tag> {
         self.heading
             .tags
             .iter()
-            .filter(|t| t.category == category)
+            .filter(|t| t.sigil == sigil)
             .collect()
     }
+
+    /// Turn tags whose sigil is not `known` back into simple tags, keeping the sigil in their name
+    pub fn simplify_tags(&mut self, known: &dyn Fn(char) -> bool) {
+        for tag in &mut self.heading.tags {
+            if let Some(sigil) = tag.sigil.filter(|&c| !known(c)) {
+                tag.name.insert(0, sigil);
+                tag.sigil = None;
+            }
+        }
+        self.subsections
+            .iter_mut()
+            .for_each(|s| s.simplify_tags(known));
+    }
 }
 
 impl Section {
@@ -111,41 +532,524 @@ impl Section {
     pub fn title(&self) -> String {
         self.heading.text.clone()
     }
+
+    /// This section or the first below it with the given heading anchor
+    pub fn find_section(&self, anchor: &str) -> Option<&Section> {
+        if self.heading.anchor == anchor {
+            return Some(self);
+        }
+        self.subsections.iter().find_map(|s| s.find_section(anchor))
+    }
+
+    /// Blueprint the section was read from, for a section of page `page`
+    pub fn source<'a>(&'a self, page: &'a str) -> &'a str {
+        self.source.as_deref().unwrap_or(page)
+    }
+
+    /// Move an included section and its subsections `by` ranks down
+    fn shift(&mut self, by: usize, source: &str) {
+        self.heading.rank += by;
+        self.source.get_or_insert_with(|| source.to_string());
+        for sub in &mut self.subsections {
+            sub.shift(by, source);
+        }
+    }
+
+    /// Tasks in the section body and all its subsections
+    pub fn progress(&self) -> Progress {
+        self.body
+            .iter()
+            .filter_map(|par| match &par.node {
+                Paragraph::List(l) => Some(l.progress()),
+                _ => None,
+            })
+            .chain(self.subsections.iter().map(Section::progress))
+            .sum()
+    }
+}
+
+/// Workflow state of a document
+#[derive(Clone, Copy, PartialEq, Eq, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Draft,
+    Review,
+    Approved,
+    Obsolete,
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Status::Draft => write!(f, "draft"),
+            Status::Review => write!(f, "review"),
+            Status::Approved => write!(f, "approved"),
+            Status::Obsolete => write!(f, "obsolete"),
+        }
+    }
+}
+
+/// One `key: value` line of the front matter
+#[derive(Clone, Default, PartialEq, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Field {
+    pub key: String,
+    pub value: String,
+    pub location: Location,
+}
+
+impl Locate for Field {
+    fn locate(&mut self, index: &LineIndex) {
+        index.resolve(&mut self.location);
+    }
+}
+
+/// Document metadata, from the `---` delimited front matter at the top of a file
+#[derive(Clone, Default, PartialEq, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Meta {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub version: Option<String>,
+    pub status: Option<Status>,
+    /// Document identifier
+    pub id: Option<String>,
+    /// Fields with keys outside [`Meta::KEYS`], kept so they can be reported
+    pub unknown: Vec<Field>,
+    /// Fields whose value their key does not allow, such as an unknown status
+    pub invalid: Vec<Field>,
+}
+
+impl Meta {
+    /// Keys of the front matter schema, in canonical order
+    pub const KEYS: &'static [&'static str] = &["title", "author", "version", "status", "id"];
+
+    /// Metadata from the fields in file order; a repeated key takes its last value
+    fn new(fields: Vec<Field>) -> Meta {
+        let mut meta = Meta::default();
+        for field in fields {
+            let value = Some(field.value.clone());
+            match field.key.as_str() {
+                "title" => meta.title = value,
+                "author" => meta.author = value,
+                "version" => meta.version = value,
+                "status" => match parse::status(&field.value) {
+                    Ok(status) => meta.status = Some(status),
+                    Err(_) => meta.invalid.push(field),
+                },
+                "id" => meta.id = value,
+                _ => meta.unknown.push(field),
+            }
+        }
+        meta
+    }
+
+    /// Known fields that are set, as `(key, value)` in [`Meta::KEYS`] order
+    pub fn known(&self) -> Vec<(&'static str, String)> {
+        [
+            ("title", self.title.clone()),
+            ("author", self.author.clone()),
+            ("version", self.version.clone()),
+            ("status", self.status.map(|s| s.to_string())),
+            ("id", self.id.clone()),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| Some((key, value?)))
+        .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.known().is_empty() && self.unknown.is_empty() && self.invalid.is_empty()
+    }
+}
+
+/// Lowercase words of `text` joined by `-`, the default anchor of a heading
+pub fn slug(text: &str) -> String {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join("-")
 }
 
 #[derive(Clone, Default, PartialEq, Debug, serde::Serialize, serde::Deserialize)]
 pub struct Blueprint {
     pub name: String,
+    pub meta: Meta,
     pub root: Section,
+    /// Names of the blueprints spliced in by `!include`, directly or not
+    pub includes: Vec<String>,
 }
 
 impl Blueprint {
-    pub fn parse_file(file: &std::path::Path) -> Result<Blueprint> {
-        Blueprint::parse(
-            file.file_name()
-                .context("no file")?
-                .to_str()
-                .context("no file")?,
-            &std::fs::read_to_string(file)?,
-        )
+    /// Parse a file below `root`, naming the blueprint after its path relative to `root`
+    pub fn parse_file(root: &std::path::Path, file: &std::path::Path) -> Result<Blueprint> {
+        let text =
+            std::fs::read_to_string(file).with_context(|| format!("reading {}", file.display()))?;
+        Blueprint::parse_source(root, file, &text)
+    }
+
+    /// Parse the already read `text` of a file below `root`, like [`Blueprint::parse_file`]
+    pub fn parse_source(
+        root: &std::path::Path,
+        file: &std::path::Path,
+        text: &str,
+    ) -> Result<Blueprint> {
+        Blueprint::parse(&crate::source::relative_name(root, file), text).map_err(|err| {
+            match err.downcast::<crate::diagnostic::Diagnostics>() {
+                Ok(diags) => diags.in_file(&file.display().to_string()).into(),
+                Err(err) => err,
+            }
+        })
+    }
+
+    /// Parse a file below `root` like [`Blueprint::parse_file`], replacing each `!include` with the
+    /// sections of the file it names
+    ///
+    /// An included file's paths are relative to the including one. Its sections become
+    /// subsections of the section holding the directive, ahead of that section's own, with their
+    /// ranks shifted to fit.
+    pub fn load(root: &std::path::Path, file: &std::path::Path) -> Result<Blueprint> {
+        Blueprint::load_included(root, file, &mut vec![], &mut Default::default())
+    }
+
+    /// Load the pages among `files`, returning them with the errors of those that failed
+    ///
+    /// A file another one includes is a fragment rather than a page, so its errors are reported
+    /// once, through the page including it. Fragments no page reaches, such as those in an include
+    /// cycle, are loaded on their own so their problems still surface. A file that cannot be read
+    /// is reported against itself without stopping the others.
+    pub fn load_pages(
+        root: &std::path::Path,
+        files: &[std::path::PathBuf],
+    ) -> (Vec<Blueprint>, Vec<crate::diagnostic::Diagnostic>) {
+        fn targets(file: &std::path::Path, sec: &Section, out: &mut Vec<std::path::PathBuf>) {
+            for par in &sec.body {
+                if let Paragraph::Include(path) = &par.node {
+                    out.extend(file.with_file_name(path).canonicalize());
+                }
+            }
+            sec.subsections.iter().for_each(|s| targets(file, s, out));
+        }
+
+        let canonical = |file: &std::path::Path| file.canonicalize().unwrap_or(file.to_path_buf());
+        let mut graph = std::collections::HashMap::new();
+        for file in files {
+            let mut out = vec![];
+            if let Ok(bp) = Blueprint::parse_file(root, file) {
+                targets(file, &bp.root, &mut out);
+            }
+            graph.insert(canonical(file), out);
+        }
+        let fragments = graph
+            .values()
+            .flatten()
+            .cloned()
+            .collect::<std::collections::HashSet<_>>();
+        let mut reached = std::collections::HashSet::new();
+        let reach = |from: std::path::PathBuf, reached: &mut std::collections::HashSet<_>| {
+            let mut stack = vec![from];
+            while let Some(file) = stack.pop() {
+                if reached.insert(file.clone()) {
+                    stack.extend(graph.get(&file).into_iter().flatten().cloned());
+                }
+            }
+        };
+        for file in files {
+            let canonical = canonical(file);
+            if !fragments.contains(&canonical) {
+                reach(canonical, &mut reached);
+            }
+        }
+
+        let mut pages = vec![];
+        let mut errors = vec![];
+        for file in files {
+            let canonical = canonical(file);
+            if fragments.contains(&canonical) {
+                if reached.contains(&canonical) {
+                    continue;
+                }
+                reach(canonical, &mut reached);
+            }
+            match Blueprint::load(root, file) {
+                Ok(bp) => pages.push(bp),
+                Err(err) => match err.downcast::<crate::diagnostic::Diagnostics>() {
+                    Ok(diags) => errors.extend(diags.0),
+                    Err(err) => errors.push(crate::diagnostic::Diagnostic::error(
+                        &file.display().to_string(),
+                        Location::default(),
+                        &format!("{:#}", err),
+                    )),
+                },
+            }
+        }
+        (pages, errors)
     }
 
+    /// `chain` holds the canonical paths of the files including this one, outermost first, and
+    /// `seen` every file already spliced into the page, with where it was included
+    fn load_included(
+        root: &std::path::Path,
+        file: &std::path::Path,
+        chain: &mut Vec<std::path::PathBuf>,
+        seen: &mut std::collections::HashMap<std::path::PathBuf, String>,
+    ) -> Result<Blueprint> {
+        let mut bp = Blueprint::parse_file(root, file)?;
+        let mut errors = vec![];
+        chain.push(file.canonicalize()?);
+        let mut includes = vec![];
+        Blueprint::splice(
+            root,
+            file,
+            &mut bp.root,
+            chain,
+            seen,
+            &mut includes,
+            &mut errors,
+        );
+        chain.pop();
+        errors.extend(bp.assign_anchors(&|name| root.join(name).display().to_string()));
+        if !errors.is_empty() {
+            return Err(crate::diagnostic::Diagnostics(errors).into());
+        }
+        bp.includes = includes;
+        Ok(bp)
+    }
+
+    fn splice(
+        root: &std::path::Path,
+        file: &std::path::Path,
+        sec: &mut Section,
+        chain: &mut Vec<std::path::PathBuf>,
+        seen: &mut std::collections::HashMap<std::path::PathBuf, String>,
+        includes: &mut Vec<String>,
+        errors: &mut Vec<crate::diagnostic::Diagnostic>,
+    ) {
+        use crate::diagnostic::Diagnostic;
+
+        let mut spliced = vec![];
+        for par in std::mem::take(&mut sec.body) {
+            let path = match &par.node {
+                Paragraph::Include(path) => file.with_file_name(path),
+                _ => {
+                    sec.body.push(par);
+                    continue;
+                }
+            };
+            let error = |message: &str| {
+                Diagnostic::error(&file.display().to_string(), par.location, message)
+            };
+            let canonical = match path.canonicalize() {
+                Ok(canonical) => canonical,
+                Err(err) => {
+                    errors.push(error(&format!(
+                        "cannot read included file {}: {}",
+                        path.display(),
+                        err
+                    )));
+                    continue;
+                }
+            };
+            if let Some(start) = chain.iter().position(|f| *f == canonical) {
+                let cycle = chain[start..]
+                    .iter()
+                    .chain(std::iter::once(&canonical))
+                    .map(|f| f.display().to_string())
+                    .collect::<Vec<_>>();
+                errors.push(error(&format!("include cycle: {}", cycle.join(" -> "))));
+                continue;
+            }
+            let at = format!("{}:{}", file.display(), par.location);
+            if let Some(first) = seen.insert(canonical, at.clone()) {
+                errors.push(
+                    error(&format!(
+                        "{} is already included in this page",
+                        path.display()
+                    ))
+                    .with_note(&format!("first included from {}", first)),
+                );
+                continue;
+            }
+            match Blueprint::load_included(root, &path, chain, seen) {
+                Ok(included) => {
+                    for mut sub in included.root.subsections {
+                        sub.shift(sec.heading.rank, &included.name);
+                        spliced.push(sub);
+                    }
+                    includes.push(included.name);
+                    includes.extend(included.includes);
+                }
+                Err(err) => match err.downcast::<crate::diagnostic::Diagnostics>() {
+                    Ok(diags) => errors.extend(
+                        diags
+                            .0
+                            .into_iter()
+                            .map(|d| d.with_note(&format!("included from {}", at))),
+                    ),
+                    Err(err) => errors.push(error(&format!("{:#}", err))),
+                },
+            }
+        }
+        for sub in &mut sec.subsections {
+            Blueprint::splice(root, file, sub, chain, seen, includes, errors);
+        }
+        sec.subsections.splice(0..0, spliced);
+    }
+
+    /// Parse `input`, reporting every error in it rather than just the first
+    ///
+    /// After each failure the offending line is blanked out and parsing restarts, until either the
+    /// remaining text parses or no further progress can be made.
     pub fn parse(name: &str, input: &str) -> Result<Blueprint> {
-        Ok(parse::blueprint(input, name)?)
+        use crate::diagnostic::{Diagnostic, Diagnostics};
+
+        let mut errors = vec![];
+        let mut text = input.to_string();
+        loop {
+            match parse::blueprint(&text, name) {
+                Ok(mut bp) if errors.is_empty() => {
+                    let index = LineIndex::new(input);
+                    bp.meta.unknown.locate(&index);
+                    bp.meta.invalid.locate(&index);
+                    bp.root.subsections.locate(&index);
+                    errors = bp.repeated_attributes();
+                    errors.extend(bp.assign_anchors(&str::to_string));
+                    if errors.is_empty() {
+                        return Ok(bp);
+                    }
+                    errors = errors.into_iter().map(|d| d.with_source(input)).collect();
+                    break;
+                }
+                Ok(_) => break,
+                Err(err) => {
+                    let diag = Diagnostic::from_parse_error(name, &text, &err).with_source(input);
+                    let line = diag.location.line;
+                    errors.push(diag);
+                    if !Self::blank_line(&mut text, line) {
+                        break;
+                    }
+                }
+            }
+        }
+        Err(Diagnostics(errors).into())
+    }
+
+    /// Replace a 1-based line with spaces, keeping every offset intact
+    fn blank_line(text: &mut String, line: usize) -> bool {
+        let start = match line {
+            0 => return false,
+            1 => 0,
+            _ => match text.match_indices('\n').nth(line - 2) {
+                Some((i, _)) => i + 1,
+                None => return false,
+            },
+        };
+        let end = text[start..].find('\n').map_or(text.len(), |i| start + i);
+        if text[start..end].trim().is_empty() {
+            return false;
+        }
+        let blank = " ".repeat(end - start);
+        text.replace_range(start..end, &blank);
+        true
     }
 
+    /// Title from the front matter, else the first top-level heading
     pub fn title(&self) -> Option<String> {
-        Some(self.root.subsections.get(0)?.heading.text.clone())
+        if let Some(title) = &self.meta.title {
+            return Some(title.clone());
+        }
+        Some(self.root.subsections.first()?.heading.text.clone())
     }
-}
 
-impl Into<crate::template::Page> for &Blueprint {
-    fn into(self) -> crate::template::Page {
-        crate::template::Page {
-            file: ("bp_".to_string() + &self.name + ".html").into(),
-            title: self.title().unwrap_or("Untitled".to_string()),
-            content: self.clone(),
+    /// Tag attributes given more than once, at each repeat
+    fn repeated_attributes(&self) -> Vec<crate::diagnostic::Diagnostic> {
+        fn collect(name: &str, sec: &Section, out: &mut Vec<crate::diagnostic::Diagnostic>) {
+            for tag in &sec.heading.tags {
+                out.extend(tag.repeated.iter().map(|(key, location)| {
+                    crate::diagnostic::Diagnostic::error(
+                        name,
+                        *location,
+                        &format!("duplicate attribute {} in tag {}", key, tag.name),
+                    )
+                    .with_hint("each attribute may be given once per tag")
+                }));
+            }
+            sec.subsections.iter().for_each(|s| collect(name, s, out));
         }
+
+        let mut out = vec![];
+        collect(&self.name, &self.root, &mut out);
+        out
+    }
+
+    /// Set the anchor of every heading, in document order; explicit ids are never renamed, and an
+    /// id used by more than one heading is reported at each later use, in the file `path` gives for
+    /// its source
+    fn assign_anchors(
+        &mut self,
+        path: &dyn Fn(&str) -> String,
+    ) -> Vec<crate::diagnostic::Diagnostic> {
+        fn reserve(
+            page: &str,
+            path: &dyn Fn(&str) -> String,
+            sec: &Section,
+            first: &mut std::collections::HashMap<String, String>,
+            errors: &mut Vec<crate::diagnostic::Diagnostic>,
+        ) {
+            if let Some(id) = &sec.heading.id {
+                let file = path(sec.source(page));
+                let at = format!("{}:{}", file, sec.heading.location);
+                match first.get(id) {
+                    Some(first) => errors.push(
+                        crate::diagnostic::Diagnostic::error(
+                            &file,
+                            sec.heading.location,
+                            &format!("duplicate heading id {}", id),
+                        )
+                        .with_note(&format!("first used at {}", first)),
+                    ),
+                    None => {
+                        first.insert(id.clone(), at);
+                    }
+                }
+            }
+            sec.subsections
+                .iter()
+                .for_each(|s| reserve(page, path, s, first, errors));
+        }
+        fn assign(sec: &mut Section, taken: &mut std::collections::HashSet<String>) {
+            sec.heading.anchor = match &sec.heading.id {
+                Some(id) => id.clone(),
+                None => {
+                    let base = match slug(&sec.heading.text) {
+                        s if s.is_empty() => "section".to_string(),
+                        s => s,
+                    };
+                    let mut anchor = base.clone();
+                    for n in 1.. {
+                        if !taken.contains(&anchor) {
+                            break;
+                        }
+                        anchor = format!("{}-{}", base, n);
+                    }
+                    taken.insert(anchor.clone());
+                    anchor
+                }
+            };
+            sec.subsections.iter_mut().for_each(|s| assign(s, taken));
+        }
+
+        let mut first = std::collections::HashMap::new();
+        let mut errors = vec![];
+        self.root
+            .subsections
+            .iter()
+            .for_each(|s| reserve(&self.name, path, s, &mut first, &mut errors));
+        let mut taken = first.into_keys().collect();
+        self.root
+            .subsections
+            .iter_mut()
+            .for_each(|s| assign(s, &mut taken));
+        errors
     }
 }
 
@@ -155,55 +1059,109 @@ peg::parser! {
         rule _() -> &'input str = quiet!{$([' ' | '\t']*)}
         rule __() -> &'input str = quiet!{$(_ ['\n' | '\r']+)} / expected!("eol")
         rule ___() -> &'input str = quiet!{$(_ (![_] / __))} / expected!("eol / eof")
+        // A single line ending, leaving any blank lines after it
+        rule eol() -> &'input str = quiet!{$(_ ("\r\n" / ['\n' | '\r'] / ![_]))} / expected!("eol / eof")
+        rule located<T>(r: rule<T>) -> Located<T>
+            = s:position!() node:r() e:position!() {
+                Located { node, location: Location::new(s, e) }
+            }
 
         // Span syntax
+        // Text ending the current span sequence, such as the `|` closing a block line
+        rule stop(except: &'static str) = #{|input, pos| {
+            if !except.is_empty() && input[pos..].starts_with(except) {
+                peg::RuleResult::Matched(pos + except.len(), ())
+            } else {
+                peg::RuleResult::Failed
+            }
+        }}
         rule span_decoration() -> char
             = ['*' | '/' | '~']
-        rule span_decorated() -> Span
-            = open:span_decoration() s:$(span_plain(<span_decoration()>)) close:span_decoration() {?
-                if open == close {
-                    Ok(Span::from_decoration(open, s.to_string()))
-                } else {
-                    Err("mismatched span delimiters")
+        rule word_char() = [c if c.is_alphanumeric() || c == '_']
+        rule span_escape() -> char
+            = "\\" c:['*' | '/' | '~' | '|' | '[' | ']' | '`' | '-' | '.' | '!' | '\\'] { c }
+        // A delimiter closes when it follows text and is not followed by a word character
+        rule span_close(delimiter: char) = [c if c == delimiter] !word_char()
+        // Plain text inside a decorated span, which ends at the closing delimiter
+        rule span_inner_plain(delimiter: char, except: &'static str) -> Span
+            = s:(
+                c:span_escape() { c.to_string() }
+                // A delimiter after whitespace does not close
+                / s:$([' ' | '\t']+ [c if c == delimiter]?) { s.to_string() }
+                / s:$(word_char() !span_close(delimiter) span_decoration()) { s.to_string() }
+                / !span_close(delimiter) !__ !stop(except) !span_code() !span_ref() !span_link()
+                  !span_decorated(except) !"\\" c:$([_]) {
+                    c.to_string()
                 }
+            )+ {
+                Span::Plain(s.concat())
+            }
+        // A delimiter opens when followed by text; plain text consumes delimiters directly after a
+        // word character, so those never open
+        rule span_decorated(except: &'static str) -> Span
+            = open:span_decoration() !([' ' | '\t'] / __)
+              s:(!span_close(open) s:(span_code() / span_decorated(except) / span_ref() / span_link()
+                  / span_inner_plain(open, except)) { s })+
+              span_close(open) {
+                Span::from_decoration(open, s)
+            }
+        rule span_ref() -> Span
+            = "[[req:" target:$([^ ']' | '#' | '\n']+) "]]" {
+                Span::Ref(Reference { target: target.to_string(), ..Default::default() })
+            }
+            / "[[page:" target:$([^ ']' | '#' | '\n']*) section:("#" s:$([^ ']' | '\n']+) { s })? "]]" {
+                Span::Ref(Reference {
+                    kind: RefKind::Page,
+                    target: target.to_string(),
+                    section: section.map(str::to_string),
+                    ..Default::default()
+                })
             }
         rule span_link() -> Span
-            = "[" text:$([^ ']' | '\n']+) "](" link:$([^ ')' | '\n']+) ")" {
-                Span::Link((text.to_string(), link.to_string()))
+            = "[" text:(span_code() / span_decorated("]") / span_plain("]"))+ "](" link:$([^ ')' | '\n']+) ")" {
+                Span::Link(text, link.to_string())
             }
-        rule span_plain<T>(except: rule<T>) -> Span
-            = s:$((!__ !except() !span_link() [_])+) {
-                Span::Plain(s.to_string())
+        rule span_code() -> Span
+            = "`" c:$([^ '`' | '\n' | '\r']+) "`" { Span::Code(c.to_string()) }
+        rule span_plain(except: &'static str) -> Span
+            = s:(
+                c:span_escape() { c.to_string() }
+                / s:$(word_char() span_decoration()) { s.to_string() }
+                / !__ !stop(except) !span_code() !span_ref() !span_link() !span_decorated(except) !"\\" c:$([_]) {
+                    c.to_string()
+                }
+            )+ {
+                Span::Plain(s.concat())
             }
-        rule span_except<T>(except: rule<T>) -> Span
-            = span_decorated() / span_link() / span_plain(<except()>)
-        pub rule span() -> Span
-            = span_decorated() / span_link() / span_plain(<span_decoration()>)
+        rule span_except(except: &'static str) -> Located<Span>
+            = located(<span_code() / span_decorated(except) / span_ref() / span_link() / span_plain(except)>)
+        pub rule span() -> Located<Span>
+            = span_except("")
 
         // Body syntax
         rule spans() -> Paragraph
             = __* !['#'] s:span()+ ___ { Paragraph::Spans(s) }
-        rule block_line() -> Vec<Span>
-            = __* "|" s:span_except(<['|']>)+ "|" ___ {
+        rule block_line() -> Vec<Located<Span>>
+            = __* "|" s:span_except("|")+ "|" ___ {
                 s
             }
         rule block() -> Paragraph
             = lines:block_line()+ {
                 Paragraph::Block(lines.iter().flatten().cloned().collect())
             }
-        rule note_line() -> Vec<Span>
-            = __* "||" s:span_except(<"||">)+ "||" ___ {
+        rule note_line() -> Vec<Located<Span>>
+            = __* "||" s:span_except("||")+ "||" ___ {
                 s
             }
         rule note() -> Paragraph
             = lines:note_line()+ {
                 Paragraph::Note(lines.iter().flatten().cloned().collect())
             }
-        rule table_row() -> Vec<Vec<Span>>
-            = __* s:(span_except(<(['|'] / span_decoration())>)+) **<2,> "|" ___ {
+        rule table_row() -> Vec<Vec<Located<Span>>>
+            = __* s:(span_except("|")+) **<2,> "|" ___ {
                 s
             }
-        rule table_heading() -> Vec<Vec<Span>>
+        rule table_heading() -> Vec<Vec<Located<Span>>>
             = row:table_row() _ sep:((['-' | ' ']+) **<2,> "|") ___ {?
                 if sep.len() == row.len() {
                     Ok(row)
@@ -216,38 +1174,113 @@ peg::parser! {
                 heading: head.unwrap_or_default(),
                 body: rows
             })}
-        pub rule body() -> Vec<Paragraph>
-            = (block() / note() / table() / spans())+
-
-        // Tag syntax
-        rule tag_category() -> TagCategory
-            = c:(['?' | '=']?) {
-                match c {
-                    Some('?') => TagCategory::Requires,
-                    Some('=') => TagCategory::Satisfies,
-                    None => TagCategory::Simple,
-                    Some(_) => unreachable!()
+        rule html() -> Paragraph
+            = __* "<<<" _ ['\n' | '\r']+ h:$((!(_ ">>>") [^ '\n']* "\n")*) _ ">>>" ___ {
+                Paragraph::Html(h.to_string())
+            }
+        // Width of the indentation at the current position, without consuming it
+        rule indentation() -> usize = #{|input, pos| {
+            peg::RuleResult::Matched(pos, input[pos..].chars().take_while(|c| *c == ' ').count())
+        }}
+        // Whether the list item at the current position is numbered, without consuming it
+        rule ordered() -> bool = #{|input, pos| {
+            let first = input[pos..].trim_start_matches(' ').chars().next();
+            peg::RuleResult::Matched(pos, first.is_some_and(|c| c.is_ascii_digit()))
+        }}
+        rule list_marker() -> bool
+            = ['-' | '*'] { false }
+            / ['0'..='9']+ "." { true }
+        rule task() -> bool
+            = "[" c:[' ' | 'x' | 'X'] "]" [' ' | '\t']+ { c != ' ' }
+        // Whether the item is numbered, and its checkbox; a bare checkbox starts a bulleted item
+        rule list_head() -> (bool, Option<bool>)
+            = o:list_marker() [' ' | '\t']+ t:task()? { (o, t) }
+            / t:task() { (false, Some(t)) }
+        rule list_item(indent: usize, ordered: bool) -> ListItem
+            = task:quiet!{
+                n:indentation() " "* h:list_head() {?
+                    if n == indent && h.0 == ordered { Ok(h.1) } else { Err("list item") }
                 }
+              } s:span()+ eol() l:sublist(indent)? {
+                ListItem { spans: s, task, list: l }
+            }
+        rule deeper(indent: usize) -> usize
+            = quiet!{ n:indentation() {? if n > indent { Ok(n) } else { Err("list item") } } }
+        rule sublist(indent: usize) -> List
+            = n:deeper(indent) l:list_at(n) { l }
+        rule list_at(indent: usize) -> List
+            = ordered:ordered() items:list_item(indent, ordered)+ {
+                List { ordered, items }
             }
+        rule list() -> Paragraph
+            = n:indentation() l:list_at(n) { Paragraph::List(l) }
+        rule include() -> Paragraph
+            = __* "!include" [' ' | '\t']+ path:$([^ '\n' | '\r']+) ___ {
+                Paragraph::Include(path.trim_end().to_string())
+            }
+        rule figure() -> Paragraph
+            = __* "![" caption:span_except("]")* "](" path:$([^ ')' | '\n']+) ")" ___ {
+                Paragraph::Figure(Figure { caption, path: path.to_string(), number: 0 })
+            }
+        rule code() -> Paragraph
+            = __* "```" _ l:$([^ ' ' | '\t' | '\n' | '\r' | '`']*) _ ['\n' | '\r']
+              c:$((!(_ "```") [^ '\n']* "\n")*) _ "```" ___ {
+                Paragraph::Code(l.to_string(), c.to_string())
+            }
+        pub rule body() -> Vec<Located<Paragraph>>
+            = (__* p:located(<code() / html() / list() / include() / figure() / block() / note() / table() / spans()>) { p })+
+
+        // Tag syntax
+        rule tag_sigil() -> char
+            = [c if SIGILS.contains(c)]
+        rule attribute_key() -> &'input str
+            = $(['a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-']+)
+        rule attribute_value() -> &'input str
+            = "\"" v:$([^ '"' | '\n' | '\r']*) "\"" { v }
+            / $([^ ' ' | '\t' | ']' | ',' | '"' | '\n' | '\r']+)
+        rule attribute() -> (&'input str, &'input str)
+            = k:attribute_key() "=" v:attribute_value() { (k, v) }
+        rule tag_word() = !attribute() [^ ']' | ',' | ' ']+
         rule tag() -> Tag
-            = c:tag_category() t:$([^ ']' | ',']+) {
+            = _ s:position!() c:tag_sigil()? t:$(tag_word() ++ (" "+))
+              a:(" "+ s:position!() a:attribute() e:position!() { (a, Location::new(s, e)) })*
+              e:position!() _ {
+                let mut attributes = std::collections::BTreeMap::new();
+                let mut repeated = vec![];
+                for ((key, value), location) in a {
+                    if attributes.contains_key(key) {
+                        repeated.push((key.to_string(), location));
+                    } else {
+                        attributes.insert(key.to_string(), value.to_string());
+                    }
+                }
                 Tag {
-                    category: c,
-                    name: t.to_string()
+                    sigil: c,
+                    name: t.to_string(),
+                    attributes,
+                    repeated,
+                    location: Location::new(s, e),
                 }
             }
         pub rule tags() -> Vec<Tag>
             = ['['] t:(tag() ** ",") [']'] { t }
 
         // Heading syntax
-        rule heading_words() -> &'input str = $(([^ '\n' | '\r' | '[' | ' ']+) ++ (" "+))
+        rule heading_words() -> &'input str
+            = $(((!"{#" [^ '\n' | '\r' | '[' | ' '])+) ++ (" "+))
+        rule heading_id() -> String
+            = "{#" i:$([^ '}' | ' ' | '\t' | '\n' | '\r']+) "}" { i.to_string() }
         pub rule heading(rank: usize) -> Heading
-            = d:$("#"+) _ h:$(heading_words()) _ t:tags()? ___ {?
+            = s:position!() d:$("#"+) _ h:$(heading_words()) _ i:heading_id()? _ t:tags()?
+              e:position!() ___ {?
                 if d.len() == rank {
                     Ok(Heading {
                         rank: d.len(),
                         tags: t.unwrap_or_default(),
                         text: h.to_string(),
+                        id: i,
+                        anchor: String::new(),
+                        location: Location::new(s, e),
                     })
                 } else {
                     Err("wrong rank")
@@ -256,17 +1289,39 @@ peg::parser! {
 
         // Document syntax
         pub rule section(rank: usize) -> Section
-            = __* h:heading(rank) b:body()? sub:(section(rank + 1)*) {
+            = __* s:position!() h:heading(rank) b:body()? sub:(section(rank + 1)*) e:position!() {
                 Section {
                     heading: h,
                     body: b.unwrap_or_default(),
                     subsections: sub,
+                    location: Location::new(s, e),
+                    source: None,
                 }
             }
+        // Front matter syntax
+        pub rule status() -> Status
+            = "draft" { Status::Draft }
+            / "review" { Status::Review }
+            / "approved" { Status::Approved }
+            / "obsolete" { Status::Obsolete }
+        rule field_value() -> String
+            = v:$([^ '\n' | '\r']*) { v.trim().to_string() }
+        rule field() -> Field
+            = s:position!() k:$(['a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-']+)
+              _ ":" v:field_value() e:position!() __ {
+                Field { key: k.to_string(), value: v, location: Location::new(s, e) }
+            }
+        rule front_matter() -> Meta
+            = "---" _ __ f:(f:field() { Some(f) } / __ { None })* "---" _ __ {
+                Meta::new(f.into_iter().flatten().collect())
+            }
+
         pub rule blueprint(name: &str) -> Blueprint
-            = s:section(1)* ___ { Blueprint {
+            = m:front_matter()? s:section(1)* ___ { Blueprint {
                 name: name.to_string(),
-                root: Section::new_root(s) } }
+                meta: m.unwrap_or_default(),
+                root: Section::new_root(s),
+                includes: vec![] } }
     }
 }
 
@@ -274,6 +1329,17 @@ peg::parser! {
 mod tests {
     use super::*;
 
+    /// `assert_eq!` on a parse result, ignoring locations
+    #[track_caller]
+    fn assert_parsed<T: serde::Serialize, E: std::fmt::Display>(parsed: Result<T, E>, expected: T) {
+        assert_eq!(
+            parsed
+                .map(|tree| without_locations(&tree))
+                .map_err(|err| err.to_string()),
+            Ok(without_locations(&expected))
+        );
+    }
+
     #[test]
     fn blueprint() -> Result<()> {
         let text = "# a [b]\n## c";
@@ -287,6 +1353,7 @@ mod tests {
                         ..Default::default()
                     }],
                     text: String::from("a"),
+                    ..Default::default()
                 },
                 body: vec![],
                 subsections: vec![Section {
@@ -294,13 +1361,16 @@ mod tests {
                         rank: 2,
                         tags: vec![],
                         text: String::from("c"),
+                        ..Default::default()
                     },
                     body: vec![],
                     ..Default::default()
                 }],
+                ..Default::default()
             }]),
+            ..Default::default()
         };
-        assert_eq!(parse::blueprint(text, ""), Ok(bp));
+        assert_parsed(parse::blueprint(text, ""), bp);
 
         let text = "# a \n## b\n";
         let bp = Blueprint {
@@ -310,6 +1380,7 @@ mod tests {
                     rank: 1,
                     tags: vec![],
                     text: String::from("a"),
+                    ..Default::default()
                 },
                 body: vec![],
                 subsections: vec![Section {
@@ -317,15 +1388,41 @@ mod tests {
                         rank: 2,
                         tags: vec![],
                         text: String::from("b"),
+                        ..Default::default()
                     },
                     body: vec![],
                     ..Default::default()
                 }],
+                ..Default::default()
             }]),
+            ..Default::default()
         };
-        assert_eq!(parse::blueprint(text, ""), Ok(bp));
+        assert_parsed(parse::blueprint(text, ""), bp);
 
-        Blueprint::parse_file(std::path::Path::new("test/sample/sample.bp"))?;
+        let bp = Blueprint::parse_file(
+            std::path::Path::new("test"),
+            std::path::Path::new("test/sample/sample.bp"),
+        )?;
+        assert_eq!(bp.name, "sample/sample.bp");
+
+        Ok(())
+    }
+
+    #[test]
+    fn location() -> Result<()> {
+        let bp = Blueprint::parse("", "# a\n\nb *c*\n\n## d [e]")?;
+        let sec = &bp.root.subsections[0];
+        assert_eq!((sec.location.line, sec.location.column), (1, 1));
+        assert_eq!((sec.location.start, sec.location.end), (0, 20));
+        let par = &sec.body[0];
+        assert_eq!((par.location.line, par.location.column), (3, 1));
+        if let Paragraph::Spans(spans) = &par.node {
+            assert_eq!((spans[1].location.line, spans[1].location.column), (3, 3));
+        } else {
+            panic!("not a span paragraph");
+        }
+        let tag = &sec.subsections[0].heading.tags[0];
+        assert_eq!((tag.location.line, tag.location.column), (5, 7));
 
         Ok(())
     }
@@ -341,19 +1438,21 @@ mod tests {
                     ..Default::default()
                 }],
                 text: String::from("a"),
+                ..Default::default()
             },
             body: vec![
-                Paragraph::Spans(vec![Span::Plain("c".to_string())]),
-                Paragraph::Spans(vec![Span::Bold("d".to_string())]),
+                Paragraph::Spans(vec![Span::Plain("c".to_string()).into()]).into(),
+                Paragraph::Spans(vec![Span::Bold(vec!["d".into()]).into()]).into(),
             ],
-            subsections: Default::default(),
+            ..Default::default()
         };
         let bp = Blueprint {
             name: String::new(),
             root: Section::new_root(vec![sec.clone()]),
+            ..Default::default()
         };
-        assert_eq!(parse::section(text, 1), Ok(sec));
-        assert_eq!(parse::blueprint(text, ""), Ok(bp));
+        assert_parsed(parse::section(text, 1), sec);
+        assert_parsed(parse::blueprint(text, ""), bp);
     }
 
     #[test]
@@ -361,32 +1460,406 @@ mod tests {
         let text = "h|h\n-|-\na|b\nc|d";
         let par = Paragraph::Table(Table {
             heading: vec![
-                vec![Span::Plain("h".to_string())],
-                vec![Span::Plain("h".to_string())],
+                vec![Span::Plain("h".to_string()).into()],
+                vec![Span::Plain("h".to_string()).into()],
             ],
             body: vec![
                 vec![
-                    vec![Span::Plain("a".to_string())],
-                    vec![Span::Plain("b".to_string())],
+                    vec![Span::Plain("a".to_string()).into()],
+                    vec![Span::Plain("b".to_string()).into()],
                 ],
                 vec![
-                    vec![Span::Plain("c".to_string())],
-                    vec![Span::Plain("d".to_string())],
+                    vec![Span::Plain("c".to_string()).into()],
+                    vec![Span::Plain("d".to_string()).into()],
                 ],
             ],
         });
-        assert_eq!(parse::body(text), Ok(vec![par]));
+        assert_parsed(parse::body(text), vec![par.into()]);
     }
 
     #[test]
     fn body() {
-        let par1 = vec![Span::Plain(" a".to_string())];
-        let par2 = vec![Span::Bold(" b ".to_string())];
-        let text = &format!(" a\n\n* b *");
-        assert_eq!(
+        let par1 = vec![Span::Plain(" a".to_string()).into()];
+        let par2 = vec![Span::Bold(vec!["b c".into()]).into()];
+        let text = " a\n\n*b c*";
+        assert_parsed(
             parse::body(text),
-            Ok(vec![Paragraph::Spans(par1), Paragraph::Spans(par2)])
+            vec![Paragraph::Spans(par1).into(), Paragraph::Spans(par2).into()],
+        );
+    }
+
+    #[test]
+    fn html() {
+        assert_parsed(
+            parse::body("<<<\n<b>\n  </b>\n>>>\na"),
+            vec![
+                Paragraph::Html("<b>\n  </b>\n".to_string()).into(),
+                Paragraph::Spans(vec![Span::Plain("a".to_string()).into()]).into(),
+            ],
+        );
+        // Without a closing fence the lines are ordinary text
+        assert!(matches!(
+            parse::body("<<<\n<b>\n").as_deref(),
+            Ok([
+                Located {
+                    node: Paragraph::Spans(_),
+                    ..
+                },
+                ..
+            ])
+        ));
+    }
+
+    #[test]
+    fn code() {
+        assert_parsed(
+            parse::body("```rust\nlet a = *b;\n\n| c |\n```\nuse `*d*` \\`e`"),
+            vec![
+                Paragraph::Code("rust".to_string(), "let a = *b;\n\n| c |\n".to_string()).into(),
+                Paragraph::Spans(vec![
+                    Span::Plain("use ".to_string()).into(),
+                    Span::Code("*d*".to_string()).into(),
+                    Span::Plain(" `e`".to_string()).into(),
+                ])
+                .into(),
+            ],
+        );
+        assert_parsed(
+            parse::span("*a `b*` c*"),
+            Span::Bold(vec!["a ".into(), Span::Code("b*".to_string()), " c".into()]).into(),
+        );
+    }
+
+    #[test]
+    fn list() {
+        let item = |text: &str, list| ListItem {
+            spans: vec![Span::Plain(text.to_string()).into()],
+            list,
+            ..Default::default()
+        };
+        assert_parsed(
+            parse::body("- a\n  1. b\n  2. *c*\n* d\n1. e\n-f"),
+            vec![
+                Paragraph::List(List {
+                    ordered: false,
+                    items: vec![
+                        item(
+                            "a",
+                            Some(List {
+                                ordered: true,
+                                items: vec![
+                                    item("b", None),
+                                    ListItem {
+                                        spans: vec![Span::Bold(vec!["c".into()]).into()],
+                                        ..Default::default()
+                                    },
+                                ],
+                            }),
+                        ),
+                        item("d", None),
+                    ],
+                })
+                .into(),
+                Paragraph::List(List {
+                    ordered: true,
+                    items: vec![item("e", None)],
+                })
+                .into(),
+                Paragraph::Spans(vec![Span::Plain("-f".to_string()).into()]).into(),
+            ],
+        );
+        assert_parsed(
+            parse::body("- a\n- b\n\nc\n"),
+            vec![
+                Paragraph::List(List {
+                    ordered: false,
+                    items: vec![item("a", None), item("b", None)],
+                })
+                .into(),
+                Paragraph::Spans(vec![Span::Plain("c".to_string()).into()]).into(),
+            ],
+        );
+        let list = |text| {
+            Paragraph::List(List {
+                ordered: false,
+                items: vec![item(text, None)],
+            })
+            .into()
+        };
+        assert_parsed(parse::body("- a\n\n- b\n"), vec![list("a"), list("b")]);
+    }
+
+    #[test]
+    fn task() -> Result<()> {
+        let bp = Blueprint::parse(
+            "",
+            "# a\n\n[x] b\n[ ] c\n  - [X] d\n\n[e](f) g\n\n## h\n\n1. [ ] i\n2. [x] j\n",
+        )?;
+        let section = &bp.root.subsections[0];
+        let tasks = match &section.body[0].node {
+            Paragraph::List(l) => l.items.iter().map(|i| i.task).collect::<Vec<_>>(),
+            _ => vec![],
+        };
+        assert_eq!(tasks, vec![Some(true), Some(false)]);
+        assert!(matches!(section.body[1].node, Paragraph::Spans(_)));
+        assert_eq!(section.progress(), Progress { done: 3, total: 5 });
+        assert_eq!(
+            section.subsections[0].progress(),
+            Progress { done: 1, total: 2 }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn figure() {
+        assert_parsed(
+            parse::body("![The *big* picture](img/a.png)\n![b](c) d"),
+            vec![
+                Paragraph::Figure(Figure {
+                    caption: vec![
+                        Span::Plain("The ".to_string()).into(),
+                        Span::Bold(vec!["big".into()]).into(),
+                        Span::Plain(" picture".to_string()).into(),
+                    ],
+                    path: "img/a.png".to_string(),
+                    number: 0,
+                })
+                .into(),
+                Paragraph::Spans(vec![
+                    Span::Plain("!".to_string()).into(),
+                    Span::Link(vec!["b".into()], "c".to_string()).into(),
+                    Span::Plain(" d".to_string()).into(),
+                ])
+                .into(),
+            ],
+        );
+    }
+
+    #[test]
+    fn include() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let write = |name: &str, text: &str| -> Result<()> {
+            let path = dir.path().join(name);
+            std::fs::create_dir_all(path.parent().unwrap())?;
+            Ok(std::fs::write(path, text)?)
+        };
+        write("main.bp", "# Main\n\ntext\n!include part/a.bp\n\n## Own\n")?;
+        write("part/a.bp", "# A\n\n!include b.bp\n\n## A2\n")?;
+        write("part/b.bp", "# B\n")?;
+        let bp = Blueprint::load(dir.path(), &dir.path().join("main.bp"))?;
+        let main = &bp.root.subsections[0];
+        assert_eq!(main.body.len(), 1);
+        let headings = |sec: &Section| {
+            sec.subsections
+                .iter()
+                .map(|s| (s.title(), s.heading.rank, s.source.clone()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            headings(main),
+            vec![
+                ("A".to_string(), 2, Some("part/a.bp".to_string())),
+                ("Own".to_string(), 2, None)
+            ]
+        );
+        assert_eq!(
+            headings(&main.subsections[0]),
+            vec![
+                ("B".to_string(), 3, Some("part/b.bp".to_string())),
+                ("A2".to_string(), 3, Some("part/a.bp".to_string()))
+            ]
+        );
+        assert_eq!(bp.includes, vec!["part/a.bp", "part/b.bp"]);
+        // The directive itself survives plain parsing, for formatting
+        let bp = Blueprint::parse_file(dir.path(), &dir.path().join("main.bp"))?;
+        assert!(matches!(
+            &bp.root.subsections[0].body[1].node,
+            Paragraph::Include(path) if path == "part/a.bp"
+        ));
+
+        write("part/b.bp", "# B\n\n!include ../main.bp\n")?;
+        let err = Blueprint::load(dir.path(), &dir.path().join("main.bp")).unwrap_err();
+        let err = err.downcast::<crate::diagnostic::Diagnostics>().unwrap();
+        assert!(err.0[0].message.starts_with("include cycle: "));
+        assert_eq!(err.0[0].notes.len(), 2);
+
+        write("part/b.bp", "# B\n### C\n")?;
+        let err = Blueprint::load(dir.path(), &dir.path().join("main.bp")).unwrap_err();
+        assert!(err.to_string().ends_with(&format!(
+            "= note: included from {}:3:1\n  = note: included from {}:4:1",
+            dir.path().join("part/a.bp").display(),
+            dir.path().join("main.bp").display()
+        )));
+
+        write("part/b.bp", "# B\n")?;
+        write(
+            "main.bp",
+            "# Main\n\n!include part/a.bp\n\n## Own\n\n!include part/b.bp\n",
+        )?;
+        let err = Blueprint::load(dir.path(), &dir.path().join("main.bp")).unwrap_err();
+        let err = err.downcast::<crate::diagnostic::Diagnostics>().unwrap();
+        assert_eq!(err.0.len(), 1);
+        assert_eq!(
+            err.0[0].message,
+            format!(
+                "{} is already included in this page",
+                dir.path().join("part/b.bp").display()
+            )
+        );
+        assert_eq!(err.0[0].location.line, 7);
+
+        write("part/a.bp", "# A\n\n!include missing.bp\n")?;
+        let err = Blueprint::load(dir.path(), &dir.path().join("main.bp")).unwrap_err();
+        assert!(err.to_string().contains("cannot read included file"));
+
+        write("main.bp", "# Main {#x}\n\n!include part/b.bp\n")?;
+        write("part/b.bp", "# B\n## C {#x}\n")?;
+        let err = Blueprint::load(dir.path(), &dir.path().join("main.bp")).unwrap_err();
+        let err = err.downcast::<crate::diagnostic::Diagnostics>().unwrap();
+        assert_eq!(err.0[0].message, "duplicate heading id x");
+        assert_eq!(
+            err.0[0].file,
+            dir.path().join("part/b.bp").display().to_string()
+        );
+        assert_eq!(err.0[0].location.line, 2);
+        assert_eq!(
+            err.0[0].notes,
+            vec![format!(
+                "first used at {}:1:1",
+                dir.path().join("main.bp").display()
+            )]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn pages() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("a.bp"), "# A\n")?;
+        std::fs::write(dir.path().join("b.bp"), b"# B \xff\n")?;
+        std::fs::write(dir.path().join("c.bp"), "# C\n### D\n")?;
+        let files = ["a.bp", "b.bp", "c.bp"].map(|f| dir.path().join(f));
+        let (pages, errors) = Blueprint::load_pages(dir.path(), &files);
+        assert_eq!(pages.len(), 1);
+        assert_eq!(
+            errors
+                .iter()
+                .map(|e| (e.file.rsplit('/').next().unwrap(), e.location.line))
+                .collect::<Vec<_>>(),
+            vec![("b.bp", 0), ("c.bp", 2)],
+            "{:?}",
+            errors
+        );
+        assert!(errors[0].message.starts_with("reading "));
+
+        Ok(())
+    }
+
+    #[test]
+    fn reference() {
+        assert_parsed(
+            parse::body("see [[req:REQ-12]], *[[page:sample#sub-section]]* [[page:#a]] [[x:y]]"),
+            vec![Paragraph::Spans(vec![
+                Span::Plain("see ".to_string()).into(),
+                Span::Ref(Reference {
+                    target: "REQ-12".to_string(),
+                    ..Default::default()
+                })
+                .into(),
+                Span::Plain(", ".to_string()).into(),
+                Span::Bold(vec![Span::Ref(Reference {
+                    kind: RefKind::Page,
+                    target: "sample".to_string(),
+                    section: Some("sub-section".to_string()),
+                    ..Default::default()
+                })])
+                .into(),
+                Span::Plain(" ".to_string()).into(),
+                Span::Ref(Reference {
+                    kind: RefKind::Page,
+                    section: Some("a".to_string()),
+                    ..Default::default()
+                })
+                .into(),
+                Span::Plain(" [[x:y]]".to_string()).into(),
+            ])
+            .into()],
+        );
+        assert_eq!(
+            slug("Sub-section: the *Big* one!"),
+            "sub-section-the-big-one"
+        );
+    }
+
+    #[test]
+    fn front_matter() -> Result<()> {
+        let bp = Blueprint::parse(
+            "",
+            "---\ntitle:  Core network \nstatus: review\nowner: ops\nversion:\n---\n# A\n",
+        )?;
+        assert_eq!(bp.meta.title.as_deref(), Some("Core network"));
+        assert_eq!(bp.meta.status, Some(Status::Review));
+        assert_eq!(bp.meta.version.as_deref(), Some(""));
+        assert_eq!(bp.meta.author, None);
+        assert_eq!(bp.meta.unknown[0].key, "owner");
+        assert_eq!(bp.meta.unknown[0].location.line, 4);
+        assert_eq!(bp.title().as_deref(), Some("Core network"));
+        assert!(Blueprint::parse("", "# A\n")?.meta.is_empty());
+        let bp = Blueprint::parse("", "---\n\nstatus: done\n  \n---\n# A\n")?;
+        assert_eq!(bp.meta.status, None);
+        assert_eq!(bp.meta.invalid[0].value, "done");
+        assert_eq!(bp.meta.invalid[0].location.line, 3);
+        let err = Blueprint::parse("", "---\nid x\n---\n# A\n").unwrap_err();
+        assert_eq!(err.downcast::<crate::diagnostic::Diagnostics>()?.0.len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn anchors() -> Result<()> {
+        let bp = Blueprint::parse(
+            "",
+            "# Intro\n## Scope {#intro-1}\n## Intro\n## Intro\n# Über *all* {#top} [t]\n# ?!\n",
+        )?;
+        let mut anchors = vec![];
+        fn collect(sec: &Section, out: &mut Vec<String>) {
+            for sub in &sec.subsections {
+                out.push(sub.heading.anchor.clone());
+                collect(sub, out);
+            }
+        }
+        collect(&bp.root, &mut anchors);
+        assert_eq!(
+            anchors,
+            vec!["intro", "intro-1", "intro-2", "intro-3", "top", "section"]
+        );
+        assert_eq!(bp.root.subsections[1].heading.text, "Über *all*");
+        assert_eq!(bp.root.subsections[1].heading.tags[0].name, "t");
+
+        let err = Blueprint::parse("a.bp", "# A {#x}\n## B\n## C {#x}\n# D {#x}\n").unwrap_err();
+        let err = err.downcast::<crate::diagnostic::Diagnostics>().unwrap();
+        assert_eq!(
+            err.0
+                .iter()
+                .map(|d| (d.message.as_str(), d.location.line, d.notes.clone()))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    "duplicate heading id x",
+                    3,
+                    vec!["first used at a.bp:1:1".to_string()]
+                ),
+                (
+                    "duplicate heading id x",
+                    4,
+                    vec!["first used at a.bp:1:1".to_string()]
+                )
+            ]
         );
+
+        Ok(())
     }
 
     #[test]
@@ -411,18 +1884,129 @@ mod tests {
             rank: 1,
             tags: vec![tag.clone()],
             text: String::from("a"),
+            ..Default::default()
         };
         let tagged_text = &format!("# {} [{}]", tagged.text, tag.name);
 
-        assert_eq!(parse::heading(heading1_text, 1), Ok(heading1));
+        assert_parsed(parse::heading(heading1_text, 1), heading1);
         assert!(parse::heading(heading2_text, 1).is_err());
-        assert_eq!(parse::heading(tagged_text, 1), Ok(tagged));
+        assert_parsed(parse::heading(tagged_text, 1), tagged);
     }
 
     #[test]
     fn span() {
-        assert_eq!(parse::span("a"), Ok(Span::Plain("a".to_string())));
-        assert_eq!(parse::span("*a*"), Ok(Span::Bold("a".to_string())));
+        assert_parsed(parse::span("a"), Span::Plain("a".to_string()).into());
+        assert_parsed(parse::span("*a*"), Span::Bold(vec!["a".into()]).into());
+        assert_parsed(
+            parse::span("/a * b/"),
+            Span::Italic(vec!["a * b".into()]).into(),
+        );
+        assert_parsed(
+            parse::span("*a *b*"),
+            Span::Bold(vec!["a *b".into()]).into(),
+        );
+    }
+
+    #[test]
+    fn nested() {
+        assert_parsed(
+            parse::span("*a /b/ [~c~ d](e)*"),
+            Span::Bold(vec![
+                "a ".into(),
+                Span::Italic(vec!["b".into()]),
+                " ".into(),
+                Span::Link(
+                    vec![Span::Strikethrough(vec!["c".into()]), " d".into()],
+                    "e".to_string(),
+                ),
+            ])
+            .into(),
+        );
+        let span = parse::span("~[*a*](b)~").unwrap();
+        assert_eq!(span.text(), "a");
+        let mut links = vec![];
+        span.visit(&mut |s| {
+            if let Span::Link(_, url) = s {
+                links.push(url.clone())
+            }
+        });
+        assert_eq!(links, vec!["b"]);
+    }
+
+    #[test]
+    fn repeated() {
+        let spans = |text: &str| match &parse::body(text).unwrap()[0].node {
+            Paragraph::Spans(s) => s.iter().map(|s| s.node.clone()).collect::<Vec<_>>(),
+            _ => vec![],
+        };
+        assert_eq!(
+            spans("This is *important*. Also *this*."),
+            vec![
+                "This is ".into(),
+                Span::Bold(vec!["important".into()]),
+                ". Also ".into(),
+                Span::Bold(vec!["this".into()]),
+                ".".into(),
+            ]
+        );
+        assert_eq!(
+            spans("/this/, or /that/"),
+            vec![
+                Span::Italic(vec!["this".into()]),
+                ", or ".into(),
+                Span::Italic(vec!["that".into()]),
+            ]
+        );
+        assert_eq!(
+            spans("*a*; then *b*"),
+            vec![
+                Span::Bold(vec!["a".into()]),
+                "; then ".into(),
+                Span::Bold(vec!["b".into()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn escape() {
+        let plain = |s: &str| {
+            Ok(without_locations(&vec![Located::from(Span::Plain(
+                s.to_string(),
+            ))]))
+        };
+        for (text, expected) in [
+            ("read/write and ~5 ms", "read/write and ~5 ms"),
+            ("a * b / c", "a * b / c"),
+            ("2*3*4", "2*3*4"),
+            ("*not closed", "*not closed"),
+            ("x * b *", "x * b *"),
+            (r"\*a\* \/b\/ \~c\~ \[d](e) \\", r"*a* /b/ ~c~ [d](e) \"),
+        ] {
+            assert_eq!(
+                parse::body(text).map(|b| match &b[0].node {
+                    Paragraph::Spans(s) => without_locations(s),
+                    _ => without_locations(&()),
+                }),
+                plain(expected),
+                "{}",
+                text
+            );
+        }
+        assert_parsed(
+            parse::body(r"a\|b|c"),
+            vec![Paragraph::Table(Table {
+                heading: vec![],
+                body: vec![vec![
+                    vec![Span::Plain("a|b".to_string()).into()],
+                    vec![Span::Plain("c".to_string()).into()],
+                ]],
+            })
+            .into()],
+        );
+        assert_parsed(
+            parse::span(r"*a\*b*"),
+            Span::Bold(vec!["a*b".into()]).into(),
+        );
     }
 
     #[test]
@@ -436,13 +2020,38 @@ mod tests {
             ..Default::default()
         };
 
-        assert_eq!(
-            parse::tags(&format!("[{}]", tag.name)),
-            Ok(vec![tag.clone()])
+        assert_parsed(parse::tags(&format!("[{}]", tag.name)), vec![tag.clone()]);
+        assert_parsed(
+            parse::tags(&format!("[{}]", long_tag.name)),
+            vec![long_tag.clone()],
         );
+    }
+
+    #[test]
+    fn attributes() -> Result<()> {
+        let tags = parse::tags("[?REQ-7 priority=high owner=\"net team\", a b c=,d]")?;
+        assert_eq!(tags[0].name, "REQ-7");
         assert_eq!(
-            parse::tags(&format!("[{}]", long_tag.name)),
-            Ok(vec![long_tag.clone()])
+            tags[0].attributes.iter().collect::<Vec<_>>(),
+            vec![
+                (&"owner".to_string(), &"net team".to_string()),
+                (&"priority".to_string(), &"high".to_string()),
+            ]
         );
+        assert_eq!(tags[1].name, "a b c=");
+        assert!(tags[1].attributes.is_empty());
+        assert_eq!(tags[2].name, "d");
+        let tags = parse::tags("[x k=1 k=2]")?;
+        assert_eq!(tags[0].attributes["k"], "1");
+        assert_eq!(tags[0].repeated.len(), 1);
+
+        let err = Blueprint::parse("a.bp", "# A\n## R [?r a=1 b=2 a=3]\n").unwrap_err();
+        let err = err.downcast::<crate::diagnostic::Diagnostics>().unwrap();
+        assert_eq!(err.0.len(), 1);
+        assert_eq!(err.0[0].message, "duplicate attribute a in tag r");
+        assert_eq!((err.0[0].location.line, err.0[0].location.column), (2, 18));
+        assert_eq!(err.0[0].excerpt.as_deref(), Some("## R [?r a=1 b=2 a=3]"));
+
+        Ok(())
     }
 }
@@ -1,91 +1,1197 @@
 use crate::markup::*;
-use anyhow::Result;
+use anyhow::{Context, Result};
 
 #[derive(Clone, Default, serde::Serialize, serde::Deserialize)]
 pub struct Requirement {
     pub name: String,
+    pub page: String,
+    /// Blueprint the declaration was read from, which differs from `page` when it was included
+    pub source: String,
+    pub location: Location,
     pub content: Section,
-    pub satisfies: Vec<Tag>,
+    /// Tags of its section linking to requirements through a relation
+    pub links: Vec<Tag>,
+    /// Attributes of the declaring `[?X]` tag
+    pub attributes: std::collections::BTreeMap<String, String>,
 }
 
-impl Into<crate::template::Page> for &Requirement {
-    fn into(self) -> crate::template::Page {
-        crate::template::Page {
-            file: ("req_".to_string() + &self.name + ".html").into(),
-            title: self.content.title(),
-            content: Blueprint {
-                name: self.name.clone(),
-                root: Section::new_root(vec![self.content.clone()]),
-            },
-        }
-    }
+/// One row of the `pages.html` index
+#[derive(Clone, Default, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct PageEntry {
+    pub title: String,
+    pub file: String,
+    pub source: String,
+    pub requirements: usize,
+    pub satisfies: usize,
+    pub progress: Progress,
+    pub meta: Meta,
+}
+
+/// One row of the `requirements.html` index
+#[derive(Clone, Default, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct RequirementEntry {
+    pub name: String,
+    pub title: String,
+    pub file: String,
+    pub page: String,
+    pub page_file: String,
+    /// Anchor of the requirement's section within its page
+    pub anchor: String,
+    pub satisfied: bool,
+    pub progress: Progress,
+}
+
+/// A section carrying a simple tag, as listed on that tag's page
+#[derive(Clone, Default, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct TagSection {
+    pub title: String,
+    pub page: String,
+    pub page_file: String,
+    pub anchor: String,
+}
+
+/// Every section carrying one simple tag, rendered as `tag_*.html`
+#[derive(Clone, Default, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct TagEntry {
+    pub name: String,
+    pub file: String,
+    pub sections: Vec<TagSection>,
+}
+
+/// Context for `trace.html`
+#[derive(Clone, Default, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct TraceReport {
+    pub matrix: crate::trace::Matrix,
+    pub issues: Vec<crate::trace::Issue>,
+}
+
+/// Everything known about a set of blueprints, as written by `blueprint export`
+#[derive(Clone, Default, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Export {
+    pub pages: Vec<Blueprint>,
+    pub requirements: Vec<RequirementEntry>,
+    pub tags: Vec<TagEntry>,
+    pub trace: TraceReport,
+    pub diagnostics: Vec<crate::diagnostic::Diagnostic>,
 }
 
 pub struct Model {
-    theme: &'static crate::resource::Theme,
+    config: crate::config::Config,
     pages: std::collections::HashMap<String, Blueprint>,
     requirements: std::collections::HashMap<String, Requirement>,
+    trace: crate::trace::Trace,
+    duplicates: Vec<crate::diagnostic::Diagnostic>,
+    /// Output file of every figure image, by source file
+    assets: std::collections::BTreeMap<std::path::PathBuf, String>,
+    /// Figures whose image cannot be read
+    missing: Vec<crate::diagnostic::Diagnostic>,
+}
+
+/// Write one output file, naming it in any error
+fn write(path: std::path::PathBuf, contents: String) -> Result<()> {
+    std::fs::write(&path, contents).with_context(|| format!("writing {}", path.display()))
 }
 
 impl Model {
-    pub fn new<T: IntoIterator<Item = Blueprint>>(input: T) -> Model {
-        let pages: std::collections::HashMap<String, Blueprint> =
-            input.into_iter().map(|bp| (bp.name.clone(), bp)).collect();
+    pub fn new<T: IntoIterator<Item = Blueprint>>(
+        config: crate::config::Config,
+        input: T,
+    ) -> Model {
+        let known =
+            |c| c == config.tags.requirement_sigil || config.relations.by_sigil(c).is_some();
+        let pages: std::collections::HashMap<String, Blueprint> = input
+            .into_iter()
+            .filter(|bp| !(config.input.exclude_drafts && bp.meta.status == Some(Status::Draft)))
+            .map(|mut bp| {
+                bp.root.simplify_tags(&known);
+                (bp.name.clone(), bp)
+            })
+            .collect();
+        let mut duplicates = vec![];
+        let mut missing = vec![];
         Model {
-            theme: &crate::resource::THEME_DEFAULT,
-            requirements: Model::requirements(&pages, None),
-            pages: pages,
+            requirements: Model::requirements(&config, &pages, &mut duplicates),
+            trace: crate::trace::Trace::new(pages.values(), &config),
+            duplicates,
+            assets: Model::assets(&config, &pages, &mut missing),
+            missing,
+            pages,
+            config,
         }
     }
 
     pub fn store(&self, path: &std::path::Path) -> Result<()> {
-        self.theme.store(&path.join("theme"))?;
-        let eng = crate::template::Engine::new()?;
-        for bp in &self.pages {
-            let page: crate::template::Page = bp.1.into();
-            std::fs::write(path.join(&page.file), eng.render(&page)?)?;
+        let eng = crate::template::Engine::new(&self.config)?;
+        eng.theme()
+            .store(&path.join("theme"), self.config.output.offline)?;
+        for (source, file) in &self.assets {
+            let dest = path.join(file);
+            if let Some(dir) = dest.parent() {
+                std::fs::create_dir_all(dir)
+                    .with_context(|| format!("creating {}", dir.display()))?;
+            }
+            std::fs::copy(source, &dest)
+                .with_context(|| format!("copying {} to {}", source.display(), dest.display()))?;
+        }
+        for bp in self.pages.values() {
+            let page = self.page(bp);
+            write(path.join(&page.file), eng.render(&page)?)?;
         }
-        for req in &self.requirements {
-            let page: crate::template::Page = req.1.into();
-            std::fs::write(path.join(&page.file), eng.render(&page)?)?;
+        for req in self.requirements.values() {
+            let page = self.requirement_page(req);
+            write(path.join(&page.file), eng.render(&page)?)?;
+        }
+        write(
+            path.join("pages.html"),
+            eng.render_index("pages.html", "Pages", &self.page_index())?,
+        )?;
+        write(
+            path.join("requirements.html"),
+            eng.render_index(
+                "requirements.html",
+                "Requirements",
+                &self.requirement_index(),
+            )?,
+        )?;
+        if self.config.tags.pages {
+            let tags = self.tag_index();
+            for tag in &tags {
+                write(
+                    path.join(&tag.file),
+                    eng.render_index("tag.html", &tag.name, tag)?,
+                )?;
+            }
+            write(
+                path.join("tags.html"),
+                eng.render_index("tags.html", "Tags", &tags)?,
+            )?;
         }
+        write(
+            path.join("trace.html"),
+            eng.render_index("trace.html", "Traceability", &self.trace_report())?,
+        )?;
         Ok(())
     }
 
-    fn requirements<'a>(
-        pages: &'a std::collections::HashMap<String, Blueprint>,
-        input: Option<&'a Section>,
+    /// A blueprint as a page, named by the configured output layout
+    pub fn page(&self, bp: &Blueprint) -> crate::template::Page {
+        let mut content = bp.clone();
+        self.resolve_figures(&bp.name, &mut content.root, &mut 0);
+        self.resolve_references(&bp.name, &mut content.root);
+        crate::template::Page {
+            file: self.config.output.page_file(&bp.name).into(),
+            title: bp.title().unwrap_or("Untitled".to_string()),
+            content,
+            origin: None,
+            attributes: Default::default(),
+            relations: vec![],
+        }
+    }
+
+    /// A requirement's section as a page of its own
+    pub fn requirement_page(&self, req: &Requirement) -> crate::template::Page {
+        let mut root = Section::new_root(vec![req.content.clone()]);
+        self.resolve_figures(&req.page, &mut root, &mut 0);
+        self.resolve_references(&req.page, &mut root);
+        crate::template::Page {
+            file: self.config.output.requirement_file(&req.name).into(),
+            title: req.content.title(),
+            content: Blueprint {
+                name: req.name.clone(),
+                root,
+                ..Default::default()
+            },
+            attributes: req.attributes.clone(),
+            relations: self.relations(req),
+            origin: self.pages.get(&req.page).map(|bp| crate::template::Origin {
+                title: bp.title().unwrap_or("Untitled".to_string()),
+                link: format!(
+                    "{}#{}",
+                    self.config.output.page_file(&req.page),
+                    req.content.heading.anchor
+                ),
+            }),
+        }
+    }
+
+    /// Links from a requirement's section to other requirements, then links to it, each in
+    /// relation order
+    fn relations(&self, req: &Requirement) -> Vec<crate::template::Related> {
+        let related = |relation: &crate::config::Relation, incoming, title: &str, link| {
+            crate::template::Related {
+                label: relation.label.clone(),
+                color: relation.color.clone(),
+                directed: relation.direction == crate::config::Direction::Directed,
+                incoming,
+                title: title.to_string(),
+                link,
+            }
+        };
+        let relations = &self.config.relations;
+        let outgoing = req.links.iter().filter_map(|tag| {
+            let (_, relation) = relations.by_sigil(tag.sigil?)?;
+            Some(related(
+                relation,
+                false,
+                &tag.name,
+                self.config.output.requirement_file(&tag.name),
+            ))
+        });
+        let incoming = relations.0.iter().flat_map(|(name, relation)| {
+            self.trace
+                .linked_by(name, &req.name)
+                .iter()
+                .filter(|node| node.page != req.page || node.anchor != req.content.heading.anchor)
+                .map(move |node| {
+                    related(
+                        relation,
+                        true,
+                        &node.title,
+                        format!("{}#{}", node.page_file, node.anchor),
+                    )
+                })
+        });
+        outgoing.chain(incoming).collect()
+    }
+
+    /// Number the figures of a page in order and point them at their copied images
+    fn resolve_figures(&self, page: &str, sec: &mut Section, number: &mut usize) {
+        let source = sec.source(page).to_string();
+        for par in &mut sec.body {
+            if let Paragraph::Figure(fig) = &mut par.node {
+                *number += 1;
+                fig.number = *number;
+                if let Some(file) =
+                    self.assets
+                        .get(&figure_source(&self.config, &source, &fig.path))
+                {
+                    fig.path = file.clone();
+                }
+            }
+        }
+        for sub in &mut sec.subsections {
+            self.resolve_figures(page, sub, number);
+        }
+    }
+
+    /// Fill in the title and link of every reference on a page that can be resolved
+    fn resolve_references(&self, page: &str, sec: &mut Section) {
+        for par in &mut sec.body {
+            for span in par.node.spans_mut() {
+                span.node.visit_mut(&mut |s| {
+                    if let Span::Ref(r) = s {
+                        if let Some((title, link)) = self.resolve_reference(page, r) {
+                            r.title = Some(title);
+                            r.link = Some(link);
+                        }
+                    }
+                });
+            }
+        }
+        for sub in &mut sec.subsections {
+            self.resolve_references(page, sub);
+        }
+    }
+
+    /// Title and link of the target of a reference made on `page`
+    fn resolve_reference(&self, page: &str, r: &Reference) -> Option<(String, String)> {
+        match r.kind {
+            RefKind::Requirement => {
+                let req = self.requirements.get(&r.target)?;
+                Some((
+                    req.content.title(),
+                    self.config.output.requirement_file(&req.name),
+                ))
+            }
+            RefKind::Page => {
+                let name = match r.target.as_str() {
+                    "" => page,
+                    target => target,
+                };
+                let bp = self
+                    .pages
+                    .get(name)
+                    .or_else(|| self.pages.get(&format!("{}.bp", name)))?;
+                let file = self.config.output.page_file(&bp.name);
+                match &r.section {
+                    None => Some((bp.title().unwrap_or("Untitled".to_string()), file)),
+                    Some(section) => {
+                        let sec = bp.root.find_section(section)?;
+                        Some((sec.title(), format!("{}#{}", file, section)))
+                    }
+                }
+            }
+        }
+    }
+
+    /// Every blueprint with its requirement counts, ordered by title
+    pub fn page_index(&self) -> Vec<PageEntry> {
+        let mut out = self
+            .pages
+            .values()
+            .map(|bp| {
+                let page = self.page(bp);
+                PageEntry {
+                    title: page.title,
+                    file: page.file.to_string_lossy().to_string(),
+                    source: bp.name.clone(),
+                    requirements: Self::count_tags(&bp.root, &|tag| {
+                        tag.sigil == Some(self.config.tags.requirement_sigil)
+                    }),
+                    satisfies: Self::count_tags(&bp.root, &|tag| {
+                        tag.sigil
+                            .and_then(|s| self.config.relations.by_sigil(s))
+                            .is_some_and(|(_, relation)| relation.satisfies)
+                    }),
+                    progress: bp.root.progress(),
+                    meta: bp.meta.clone(),
+                }
+            })
+            .collect::<Vec<_>>();
+        out.sort_by(|a, b| a.title.cmp(&b.title).then(a.source.cmp(&b.source)));
+        out
+    }
+
+    /// Every requirement with its owning page and whether anything satisfies it, ordered by name
+    pub fn requirement_index(&self) -> Vec<RequirementEntry> {
+        let mut out = self
+            .requirements
+            .values()
+            .map(|req| RequirementEntry {
+                name: req.name.clone(),
+                title: req.content.title(),
+                file: self.config.output.requirement_file(&req.name),
+                page: req.page.clone(),
+                page_file: self.config.output.page_file(&req.page),
+                anchor: req.content.heading.anchor.clone(),
+                satisfied: self.is_satisfied(&req.name),
+                progress: req.content.progress(),
+            })
+            .collect::<Vec<_>>();
+        out.sort_by(|a, b| a.name.cmp(&b.name));
+        out
+    }
+
+    /// Every simple tag with the sections carrying it, ordered by name
+    pub fn tag_index(&self) -> Vec<TagEntry> {
+        fn collect(
+            output: &crate::config::Output,
+            bp: &Blueprint,
+            sec: &Section,
+            out: &mut std::collections::BTreeMap<String, TagEntry>,
+        ) {
+            for tag in sec.find_tags(None) {
+                out.entry(tag.name.clone())
+                    .or_insert_with(|| TagEntry {
+                        name: tag.name.clone(),
+                        file: output.tag_file(tag),
+                        ..Default::default()
+                    })
+                    .sections
+                    .push(TagSection {
+                        title: sec.title(),
+                        page: bp.name.clone(),
+                        page_file: output.page_file(&bp.name),
+                        anchor: sec.heading.anchor.clone(),
+                    });
+            }
+            for sub in &sec.subsections {
+                collect(output, bp, sub, out);
+            }
+        }
+
+        let mut pages = self.pages.values().collect::<Vec<_>>();
+        pages.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut out = std::collections::BTreeMap::new();
+        for bp in pages {
+            collect(&self.config.output, bp, &bp.root, &mut out);
+        }
+        out.into_values().collect()
+    }
+
+    /// Problems with the model as a whole: duplicate requirements, then traceability issues, each
+    /// reported with the severity its lint level gives it, then content problems and missing
+    /// figures
+    pub fn diagnostics(&self) -> Vec<crate::diagnostic::Diagnostic> {
+        let lint = &self.config.lint;
+        let duplicates = lint.duplicate.severity().into_iter().flat_map(|severity| {
+            self.duplicates
+                .iter()
+                .map(move |d| crate::diagnostic::Diagnostic {
+                    severity,
+                    ..d.clone()
+                })
+        });
+        let issues = self.trace.issues().into_iter().filter_map(|issue| {
+            let (level, node) = match &issue {
+                crate::trace::Issue::Unsatisfied { node, .. } => (lint.unsatisfied, node),
+                crate::trace::Issue::Dangling { node, relation, .. } => (
+                    self.config.relations.0[relation]
+                        .dangling
+                        .unwrap_or(lint.dangling),
+                    node,
+                ),
+            };
+            Some(crate::diagnostic::Diagnostic::new(
+                level.severity()?,
+                &node.source,
+                node.location,
+                &issue.to_string(),
+            ))
+        });
+        duplicates
+            .chain(issues)
+            .chain(self.content_diagnostics())
+            .chain(self.missing.iter().cloned())
+            .collect()
+    }
+
+    /// Invalid and unknown front matter fields, disallowed tag attribute values, links, raw HTML
+    /// blocks and unresolved references, by page
+    fn content_diagnostics(&self) -> Vec<crate::diagnostic::Diagnostic> {
+        fn collect(
+            model: &Model,
+            page: &str,
+            sec: &Section,
+            out: &mut Vec<crate::diagnostic::Diagnostic>,
+        ) {
+            let lint = &model.config.lint;
+            let source = sec.source(page);
+            for tag in &sec.heading.tags {
+                for (key, value) in &tag.attributes {
+                    let allowed = match model.config.attributes.get(key) {
+                        Some(attribute) if !attribute.allows(value) => &attribute.values,
+                        _ => continue,
+                    };
+                    if let Some(severity) = lint.attribute.severity() {
+                        out.push(
+                            crate::diagnostic::Diagnostic::new(
+                                severity,
+                                source,
+                                tag.location,
+                                &format!(
+                                    "{} has {}={}, which is not allowed",
+                                    tag.name, key, value
+                                ),
+                            )
+                            .with_hint(&format!(
+                                "{} may be one of {}",
+                                key,
+                                allowed.join(", ")
+                            )),
+                        );
+                    }
+                }
+            }
+            for par in &sec.body {
+                if let (Paragraph::Html(_), Some(severity)) = (&par.node, lint.raw_html.severity())
+                {
+                    out.push(crate::diagnostic::Diagnostic::new(
+                        severity,
+                        source,
+                        par.location,
+                        "raw HTML block",
+                    ));
+                }
+                for span in par.spans() {
+                    span.visit(&mut |s| match s {
+                        Span::Link(_, url) if !model.config.output.allows_link(url) => {
+                            if let Some(severity) = lint.link.severity() {
+                                out.push(crate::diagnostic::Diagnostic::new(
+                                    severity,
+                                    source,
+                                    span.location,
+                                    &format!("link to {} uses a disallowed URL scheme", url),
+                                ))
+                            }
+                        }
+                        Span::Ref(r) if model.resolve_reference(page, r).is_none() => {
+                            out.push(crate::diagnostic::Diagnostic::error(
+                                source,
+                                span.location,
+                                &format!("unresolved reference {}", r),
+                            ))
+                        }
+                        _ => {}
+                    });
+                }
+            }
+            for sub in &sec.subsections {
+                collect(model, page, sub, out);
+            }
+        }
+
+        let mut names = self.pages.keys().collect::<Vec<_>>();
+        names.sort();
+        let mut out = vec![];
+        for name in names {
+            let bp = &self.pages[name];
+            out.extend(bp.meta.invalid.iter().map(|field| {
+                crate::diagnostic::Diagnostic::error(
+                    name,
+                    field.location,
+                    &format!("invalid {} {}", field.key, field.value),
+                )
+                .with_hint("status is one of draft, review, approved or obsolete")
+            }));
+            if let Some(severity) = self.config.lint.front_matter.severity() {
+                out.extend(bp.meta.unknown.iter().map(|field| {
+                    crate::diagnostic::Diagnostic::new(
+                        severity,
+                        name,
+                        field.location,
+                        &format!("unknown front matter key {}", field.key),
+                    )
+                    .with_hint(&format!("known keys are {}", Meta::KEYS.join(", ")))
+                }));
+            }
+            collect(self, name, &bp.root, &mut out);
+        }
+        out
+    }
+
+    pub fn export(&self) -> Export {
+        let mut pages = self.pages.values().cloned().collect::<Vec<_>>();
+        pages.sort_by(|a, b| a.name.cmp(&b.name));
+        Export {
+            pages,
+            requirements: self.requirement_index(),
+            tags: self.tag_index(),
+            trace: self.trace_report(),
+            diagnostics: self.diagnostics(),
+        }
+    }
+
+    pub fn trace_report(&self) -> TraceReport {
+        TraceReport {
+            matrix: self.trace.matrix(),
+            issues: self.trace.issues(),
+        }
+    }
+
+    /// Whether any section links to the requirement through a relation with `satisfies = true`
+    pub fn is_satisfied(&self, name: &str) -> bool {
+        !self.trace.satisfied_by(name).is_empty()
+    }
+
+    fn count_tags(sec: &Section, filter: &dyn Fn(&Tag) -> bool) -> usize {
+        sec.heading.tags.iter().filter(|t| filter(t)).count()
+            + sec
+                .subsections
+                .iter()
+                .map(|s| Self::count_tags(s, filter))
+                .sum::<usize>()
+    }
+
+    /// Read every figure image to name its output file, reporting those that cannot be read
+    fn assets(
+        config: &crate::config::Config,
+        pages: &std::collections::HashMap<String, Blueprint>,
+        missing: &mut Vec<crate::diagnostic::Diagnostic>,
+    ) -> std::collections::BTreeMap<std::path::PathBuf, String> {
+        fn collect(
+            config: &crate::config::Config,
+            page: &str,
+            sec: &Section,
+            out: &mut std::collections::BTreeMap<std::path::PathBuf, String>,
+            missing: &mut Vec<crate::diagnostic::Diagnostic>,
+        ) {
+            for par in &sec.body {
+                let fig = match &par.node {
+                    Paragraph::Figure(fig) => fig,
+                    _ => continue,
+                };
+                let source = figure_source(config, sec.source(page), &fig.path);
+                if out.contains_key(&source) {
+                    continue;
+                }
+                match std::fs::read(&source) {
+                    Ok(content) => {
+                        let file = config.output.asset_file(&source, &content);
+                        out.insert(source, file);
+                    }
+                    Err(err) => missing.push(crate::diagnostic::Diagnostic::error(
+                        sec.source(page),
+                        par.location,
+                        &format!("cannot read figure {}: {}", source.display(), err),
+                    )),
+                }
+            }
+            for sub in &sec.subsections {
+                collect(config, page, sub, out, missing);
+            }
+        }
+
+        let mut names = pages.keys().collect::<Vec<_>>();
+        names.sort();
+        let mut out = std::collections::BTreeMap::new();
+        for name in names {
+            collect(config, name, &pages[name].root, &mut out, missing);
+        }
+        out
+    }
+
+    fn requirements(
+        config: &crate::config::Config,
+        pages: &std::collections::HashMap<String, Blueprint>,
+        duplicates: &mut Vec<crate::diagnostic::Diagnostic>,
     ) -> std::collections::HashMap<String, Requirement> {
-        let mut out: std::collections::HashMap<String, Requirement> =
-            std::collections::HashMap::new();
-        if let Some(sec) = input {
-            for tag in sec.find_tags(TagCategory::Requires) {
-                if let Some(old) = out.insert(
+        fn collect(
+            config: &crate::config::Config,
+            page: &str,
+            sec: &Section,
+            out: &mut std::collections::HashMap<String, Requirement>,
+            duplicates: &mut Vec<crate::diagnostic::Diagnostic>,
+        ) {
+            let sigil = config.tags.requirement_sigil;
+            for tag in sec.find_tags(Some(sigil)) {
+                if let Some(old) = out.get(&tag.name) {
+                    duplicates.push(crate::diagnostic::Diagnostic::warning(
+                        sec.source(page),
+                        tag.location,
+                        &format!(
+                            "duplicate requirement {} (first defined at {}:{})",
+                            tag.name, old.source, old.location
+                        ),
+                    ));
+                    continue;
+                }
+                out.insert(
                     tag.name.clone(),
                     Requirement {
                         name: tag.name.clone(),
+                        page: page.to_string(),
+                        source: sec.source(page).to_string(),
+                        location: tag.location,
                         content: sec.clone(),
-                        satisfies: sec
-                            .find_tags(TagCategory::Satisfies)
+                        links: sec
+                            .heading
+                            .tags
                             .iter()
-                            .cloned()
+                            .filter(|t| t.sigil.is_some_and(|s| s != sigil))
                             .cloned()
                             .collect(),
+                        attributes: tag.attributes.clone(),
                     },
-                ) {
-                    eprintln!("Duplicate requirement: {}", old.name);
-                }
+                );
             }
             for sec in &sec.subsections {
-                out.extend(Self::requirements(pages, Some(sec)))
-            }
-        } else {
-            for page in pages {
-                for sec in &page.1.root.subsections {
-                    out.extend(Self::requirements(pages, Some(sec)));
-                }
+                collect(config, page, sec, out, duplicates);
             }
         }
+
+        let mut names = pages.keys().collect::<Vec<_>>();
+        names.sort();
+        let mut out = std::collections::HashMap::new();
+        for name in names {
+            collect(config, name, &pages[name].root, &mut out, duplicates);
+        }
         out
     }
 }
+
+/// Image file of a figure on `page`, whose path is relative to the blueprint file
+fn figure_source(config: &crate::config::Config, page: &str, path: &str) -> std::path::PathBuf {
+    let root = config.input.path.clone().unwrap_or_default();
+    match root.join(page).parent() {
+        Some(dir) => dir.join(path),
+        None => root.join(path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_model() -> Result<Model> {
+        Ok(Model::new(
+            Default::default(),
+            [
+                Blueprint::parse("a.bp", "# A\n## R1 [?r1]\n## R2 [?r2]")?,
+                Blueprint::parse("b.bp", "# B [=r1]")?,
+            ],
+        ))
+    }
+
+    #[test]
+    fn page_index() -> Result<()> {
+        let index = make_model()?.page_index();
+        assert_eq!(index.len(), 2);
+        assert_eq!(index[0].title, "A");
+        assert_eq!(index[0].file, "bp_a.bp.html");
+        assert_eq!(index[0].requirements, 2);
+        assert_eq!(index[1].satisfies, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn requirement_index() -> Result<()> {
+        let index = make_model()?.requirement_index();
+        assert_eq!(
+            index
+                .iter()
+                .map(|r| (r.name.as_str(), r.page.as_str(), r.satisfied))
+                .collect::<Vec<_>>(),
+            vec![("r1", "a.bp", true), ("r2", "a.bp", false)]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn front_matter() -> Result<()> {
+        let pages = || -> Result<Vec<Blueprint>> {
+            Ok(vec![
+                Blueprint::parse("a.bp", "---\ntitle: Alpha\nowner: x\n---\n# A\n")?,
+                Blueprint::parse("b.bp", "---\nstatus: draft\n---\n# B\n")?,
+            ])
+        };
+        let model = Model::new(Default::default(), pages()?);
+        let index = model.page_index();
+        assert_eq!(index[0].title, "Alpha");
+        assert_eq!(index[1].meta.status, Some(Status::Draft));
+        let warnings = model
+            .diagnostics()
+            .into_iter()
+            .map(|d| (d.file, d.location.line, d.message))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            warnings,
+            vec![(
+                "a.bp".to_string(),
+                3,
+                "unknown front matter key owner".to_string()
+            )]
+        );
+        let model = Model::new(
+            Default::default(),
+            [Blueprint::parse("c.bp", "---\nstatus: final\n---\n# C\n")?],
+        );
+        let diags = model.diagnostics();
+        assert_eq!(diags.len(), 1);
+        assert_eq!(diags[0].message, "invalid status final");
+        assert_eq!(diags[0].severity, crate::diagnostic::Severity::Error);
+        let mut config = crate::config::Config::default();
+        config.input.exclude_drafts = true;
+        let model = Model::new(config, pages()?);
+        assert_eq!(model.page_index().len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn attributes() -> Result<()> {
+        let mut config = crate::config::Config::default();
+        config.lint.unsatisfied = crate::config::Level::Allow;
+        config.attributes.insert(
+            "priority".to_string(),
+            crate::config::Attribute {
+                values: vec!["low".to_string(), "high".to_string()],
+            },
+        );
+        let model = Model::new(
+            config,
+            [Blueprint::parse(
+                "a.bp",
+                "# A\n## R [?r priority=high owner=ops]\n## S [?s priority=urgent,=r]\n",
+            )?],
+        );
+        let page = model.requirement_page(&model.requirements["r"]);
+        assert_eq!(
+            page.attributes.iter().collect::<Vec<_>>(),
+            vec![
+                (&"owner".to_string(), &"ops".to_string()),
+                (&"priority".to_string(), &"high".to_string()),
+            ]
+        );
+        let diags = model
+            .diagnostics()
+            .into_iter()
+            .map(|d| (d.location.line, d.message, d.hint))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            diags,
+            vec![(
+                3,
+                "s has priority=urgent, which is not allowed".to_string(),
+                Some("priority may be one of low, high".to_string())
+            )]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn relations() -> Result<()> {
+        let mut config: crate::config::Config = toml::from_str(
+            r#"
+            [relations.satisfies]
+            sigil = "="
+            label = "satisfies"
+            color = "success"
+            satisfies = true
+
+            [relations.verifies]
+            sigil = "!"
+            label = "verifies"
+            dangling = "deny"
+
+            [relations.conflicts-with]
+            sigil = "~"
+            label = "conflicts with"
+            direction = "undirected"
+            "#,
+        )?;
+        config.lint.unsatisfied = crate::config::Level::Allow;
+        let model = Model::new(
+            config,
+            [Blueprint::parse(
+                "a.bp",
+                "# A\n## R [?r,~s]\n## S [?s,=r]\n## T [!r,!x,=y,^z]\n",
+            )?],
+        );
+        let related = model
+            .requirement_page(&model.requirements["r"])
+            .relations
+            .into_iter()
+            .map(|r| (r.label, r.directed, r.incoming, r.title, r.link))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            related,
+            vec![
+                (
+                    "conflicts with".to_string(),
+                    false,
+                    false,
+                    "s".to_string(),
+                    "req_s.html".to_string()
+                ),
+                (
+                    "satisfies".to_string(),
+                    true,
+                    true,
+                    "S".to_string(),
+                    "bp_a.bp.html#s".to_string()
+                ),
+                (
+                    "verifies".to_string(),
+                    true,
+                    true,
+                    "T".to_string(),
+                    "bp_a.bp.html#t".to_string()
+                ),
+            ]
+        );
+        assert!(model.is_satisfied("r"));
+        assert!(!model.is_satisfied("s"));
+        let diags = model
+            .diagnostics()
+            .into_iter()
+            .map(|d| (d.severity, d.message))
+            .collect::<Vec<_>>();
+        use crate::diagnostic::Severity;
+        assert_eq!(
+            diags,
+            vec![
+                (
+                    Severity::Warning,
+                    "unknown requirement y (satisfies link in \"T\")".to_string()
+                ),
+                (
+                    Severity::Error,
+                    "unknown requirement x (verifies link in \"T\")".to_string()
+                ),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn sigils() -> Result<()> {
+        let model = Model::new(
+            Default::default(),
+            [Blueprint::parse(
+                "a.bp",
+                "# T [@alice,~draft,!important,?r,=r]\n",
+            )?],
+        );
+        let tags = &model.pages["a.bp"].root.subsections[0].heading.tags;
+        assert_eq!(
+            tags.iter()
+                .map(|t| (t.sigil, t.name.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                (None, "@alice"),
+                (None, "~draft"),
+                (None, "!important"),
+                (Some('?'), "r"),
+                (Some('='), "r")
+            ]
+        );
+        assert!(model
+            .diagnostics()
+            .iter()
+            .all(|d| d.severity != crate::diagnostic::Severity::Error));
+
+        Ok(())
+    }
+
+    #[test]
+    fn anchors() -> Result<()> {
+        let model = Model::new(
+            Default::default(),
+            [Blueprint::parse(
+                "a.bp",
+                "# A\n## R {#req} [?r]\n## A [?s]\n",
+            )?],
+        );
+        let index = model.requirement_index();
+        assert_eq!(
+            index.iter().map(|r| r.anchor.as_str()).collect::<Vec<_>>(),
+            vec!["req", "a-1"]
+        );
+        let page = model.requirement_page(&model.requirements["s"]);
+        let origin = page.origin.unwrap();
+        assert_eq!(
+            (origin.title.as_str(), origin.link.as_str()),
+            ("A", "bp_a.bp.html#a-1")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn progress() -> Result<()> {
+        let model = Model::new(
+            Default::default(),
+            [Blueprint::parse(
+                "a.bp",
+                "# A\n\n[x] a\n\n## R [?r]\n\n- [x] b\n- [ ] c\n",
+            )?],
+        );
+        assert_eq!(
+            model.page_index()[0].progress,
+            Progress { done: 2, total: 3 }
+        );
+        assert_eq!(
+            model.requirement_index()[0].progress,
+            Progress { done: 1, total: 2 }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn figures() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir_all(dir.path().join("sub/img"))?;
+        std::fs::write(dir.path().join("sub/img/a.png"), "png")?;
+        let mut config = crate::config::Config::default();
+        config.input.path = Some(dir.path().to_path_buf());
+        let model = Model::new(
+            config,
+            [Blueprint::parse(
+                "sub/p.bp",
+                "# P\n\n![one](img/a.png)\n\n## R [?r]\n\n![two](img/a.png)\n![three](b.png)\n",
+            )?],
+        );
+        let diags = model.diagnostics();
+        assert_eq!(diags.len(), 2, "{:?}", diags);
+        assert_eq!(diags[1].severity, crate::diagnostic::Severity::Error);
+        assert!(diags[1].message.starts_with("cannot read figure"));
+        assert_eq!(diags[1].location.line, 8);
+
+        let figures = |page: crate::template::Page| {
+            let mut out = vec![];
+            fn collect(sec: &Section, out: &mut Vec<(usize, String)>) {
+                for par in &sec.body {
+                    if let Paragraph::Figure(f) = &par.node {
+                        out.push((f.number, f.path.clone()));
+                    }
+                }
+                sec.subsections.iter().for_each(|s| collect(s, out));
+            }
+            collect(&page.content.root, &mut out);
+            out
+        };
+        let file = format!("assets/a.{:016x}.png", 0x779e_6f19_5651_8c3c_u64);
+        let page = model.page(&model.pages["sub/p.bp"]);
+        assert_eq!(
+            figures(page),
+            vec![
+                (1, file.clone()),
+                (2, file.clone()),
+                (3, "b.png".to_string())
+            ]
+        );
+        let req = model.requirement_page(&model.requirements["r"]);
+        assert_eq!(figures(req)[0], (1, file.clone()));
+
+        let out = dir.path().join("out");
+        std::fs::create_dir_all(&out)?;
+        model.store(&out)?;
+        assert_eq!(std::fs::read_to_string(out.join(&file))?, "png");
+
+        Ok(())
+    }
+
+    #[test]
+    fn references() -> Result<()> {
+        let model = Model::new(
+            Default::default(),
+            [
+                Blueprint::parse(
+                    "a.bp",
+                    "# A\n\n[[req:r]] [[page:b]] [[page:b#deep-down]] [[page:#a]]\n\n## R [?r]\n",
+                )?,
+                Blueprint::parse("b.bp", "# B\n## Deep down\n\n[[req:x]] [[page:c]]\n")?,
+            ],
+        );
+        let page = model.page(&model.pages["a.bp"]);
+        let refs = page.content.root.subsections[0].body[0]
+            .spans()
+            .iter()
+            .filter_map(|s| match &s.node {
+                Span::Ref(r) => Some((r.title.clone().unwrap(), r.link.clone().unwrap())),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            refs,
+            vec![
+                ("R".to_string(), "req_r.html".to_string()),
+                ("B".to_string(), "bp_b.bp.html".to_string()),
+                (
+                    "Deep down".to_string(),
+                    "bp_b.bp.html#deep-down".to_string()
+                ),
+                ("A".to_string(), "bp_a.bp.html#a".to_string()),
+            ]
+        );
+        let errors = model
+            .diagnostics()
+            .into_iter()
+            .filter(|d| d.severity == crate::diagnostic::Severity::Error)
+            .map(|d| (d.file, d.message))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            vec![
+                (
+                    "b.bp".to_string(),
+                    "unresolved reference [[req:x]]".to_string()
+                ),
+                (
+                    "b.bp".to_string(),
+                    "unresolved reference [[page:c]]".to_string()
+                ),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn duplicate_requirement() -> Result<()> {
+        let model = Model::new(
+            Default::default(),
+            [
+                Blueprint::parse("a.bp", "# A [?r]")?,
+                Blueprint::parse("b.bp", "# B\n\n## C [x,?r]")?,
+            ],
+        );
+        let req = &model.requirements["r"];
+        assert_eq!(req.page, "a.bp");
+        assert_eq!((req.location.line, req.location.column), (1, 6));
+        let warnings = model.diagnostics();
+        assert_eq!(
+            warnings[0].message,
+            "duplicate requirement r (first defined at a.bp:1:6)"
+        );
+        assert_eq!(warnings[0].file, "b.bp");
+
+        Ok(())
+    }
+
+    #[test]
+    fn tag_index() -> Result<()> {
+        let model = Model::new(
+            Default::default(),
+            [
+                Blueprint::parse(
+                    "a.bp",
+                    "# A [x]
+## B [x,y]",
+                )?,
+                Blueprint::parse("b.bp", "# C [y,?r]")?,
+            ],
+        );
+        let index = model.tag_index();
+        assert_eq!(
+            index
+                .iter()
+                .map(|t| (t.name.as_str(), t.file.as_str(), t.sections.len()))
+                .collect::<Vec<_>>(),
+            vec![("x", "tag_x.html", 2), ("y", "tag_y.html", 2)]
+        );
+        assert_eq!(
+            index[1].sections[1],
+            TagSection {
+                title: "C".to_string(),
+                page: "b.bp".to_string(),
+                page_file: "bp_b.bp.html".to_string(),
+                anchor: "c".to_string(),
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn lint() -> Result<()> {
+        let mut config = crate::config::Config::default();
+        config.lint.unsatisfied = crate::config::Level::Deny;
+        config.lint.dangling = crate::config::Level::Allow;
+        let model = Model::new(config, [Blueprint::parse("a.bp", "# A [?r1]\n## B [=r2]")?]);
+        let diagnostics = model.diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, crate::diagnostic::Severity::Error);
+        assert!(diagnostics[0]
+            .message
+            .starts_with("unsatisfied requirement r1"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn content_lint() -> Result<()> {
+        let mut config = crate::config::Config::default();
+        config.lint.raw_html = crate::config::Level::Deny;
+        let model = Model::new(
+            config,
+            [Blueprint::parse(
+                "a.bp",
+                "# A [?r,=r]\n\n[x](javascript:x) [y](y.html)\n\n<<<\n<hr>\n>>>\n",
+            )?],
+        );
+        assert_eq!(
+            model
+                .diagnostics()
+                .iter()
+                .map(|d| (d.severity, d.message.as_str(), d.location.line))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    crate::diagnostic::Severity::Warning,
+                    "link to javascript:x uses a disallowed URL scheme",
+                    3
+                ),
+                (crate::diagnostic::Severity::Error, "raw HTML block", 5),
+            ]
+        );
+
+        Ok(())
+    }
+}
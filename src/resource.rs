@@ -1,21 +1,13 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 
 trait ExtractIf {
-    fn extract_if<S: AsRef<std::path::Path>, F>(
-        &self,
-        base_path: S,
-        pred: F,
-    ) -> std::io::Result<()>
+    fn extract_if<S: AsRef<std::path::Path>, F>(&self, base_path: S, pred: F) -> Result<()>
     where
         F: FnMut(&include_dir::DirEntry) -> bool;
 }
 
 impl<'a> ExtractIf for include_dir::Dir<'a> {
-    fn extract_if<S: AsRef<std::path::Path>, F>(
-        &self,
-        base_path: S,
-        mut pred: F,
-    ) -> std::io::Result<()>
+    fn extract_if<S: AsRef<std::path::Path>, F>(&self, base_path: S, mut pred: F) -> Result<()>
     where
         F: FnMut(&'a include_dir::DirEntry) -> bool,
     {
@@ -27,14 +19,18 @@ impl<'a> ExtractIf for include_dir::Dir<'a> {
             if pred(entry) {
                 match entry {
                     include_dir::DirEntry::Dir(d) => {
-                        std::fs::create_dir_all(&path)?;
-                        d.extract(base_path)?;
+                        std::fs::create_dir_all(&path)
+                            .with_context(|| format!("creating {}", path.display()))?;
+                        d.extract(base_path)
+                            .with_context(|| format!("writing {}", path.display()))?;
                     }
                     include_dir::DirEntry::File(f) => {
-                        if let Some(p) = f.path().parent() {
-                            std::fs::create_dir_all(p)?;
+                        if let Some(p) = path.parent() {
+                            std::fs::create_dir_all(p)
+                                .with_context(|| format!("creating {}", p.display()))?;
                         }
-                        std::fs::write(path, f.contents())?;
+                        std::fs::write(&path, f.contents())
+                            .with_context(|| format!("writing {}", path.display()))?;
                     }
                 }
             }
@@ -44,37 +40,438 @@ impl<'a> ExtractIf for include_dir::Dir<'a> {
     }
 }
 
+/// Where a theme's files come from
+enum Source {
+    Embedded(&'static include_dir::Dir<'static>),
+    /// A theme directory described by a `theme.toml` manifest
+    External(std::path::PathBuf),
+}
+
 pub struct Theme {
-    dir: &'static include_dir::Dir<'static>,
-    use_css: &'static [&'static str],
+    pub name: String,
+    source: Source,
+    use_css: Vec<String>,
+    templates: Option<std::path::PathBuf>,
 }
 
 impl Theme {
-    pub fn store(&self, path: &std::path::Path) -> Result<()> {
-        std::fs::create_dir_all(path)?;
-        self.dir.extract_if(path, |entry| {
-            entry.path().extension() == Some(std::ffi::OsStr::new("css"))
-        })?;
+    /// Copy the theme's stylesheets and assets to `path`, along with the bundled fonts and their
+    /// stylesheet when `offline`
+    pub fn store(&self, path: &std::path::Path, offline: bool) -> Result<()> {
+        std::fs::create_dir_all(path).with_context(|| format!("creating {}", path.display()))?;
+        if offline {
+            let fonts = path.join("fonts");
+            std::fs::create_dir_all(&fonts)
+                .with_context(|| format!("creating {}", fonts.display()))?;
+            FONT_DIR
+                .extract(&fonts)
+                .with_context(|| format!("writing {}", fonts.display()))?;
+            let css = path.join(FONT_CSS);
+            std::fs::write(&css, font_css())
+                .with_context(|| format!("writing {}", css.display()))?;
+        }
+        match &self.source {
+            Source::Embedded(dir) => dir.extract_if(path, |entry| {
+                entry.path().extension() == Some(std::ffi::OsStr::new("css"))
+            })?,
+            Source::External(dir) => {
+                let mut skip = vec![dir.join(MANIFEST)];
+                skip.extend(self.templates.clone());
+                copy_dir(dir, path, &skip)?;
+            }
+        }
 
         Ok(())
     }
 
     pub fn css_files(&self) -> Vec<String> {
-        self.use_css
-            .iter()
-            .map(|s| s.to_string())
-            .collect::<Vec<String>>()
+        self.use_css.clone()
+    }
+
+    /// Directory of templates replacing the built-in ones, if the theme has one
+    pub fn templates(&self) -> Option<&std::path::Path> {
+        self.templates.as_deref()
+    }
+
+    /// Load a theme directory, as described by its manifest
+    pub fn load(dir: &std::path::Path) -> Result<Theme> {
+        let file = dir.join(MANIFEST);
+        let text = std::fs::read_to_string(&file)
+            .with_context(|| format!("reading theme manifest {}", file.display()))?;
+        let manifest: Manifest = toml::from_str(&text)
+            .with_context(|| format!("parsing theme manifest {}", file.display()))?;
+        for css in &manifest.css {
+            anyhow::ensure!(
+                dir.join(css).is_file(),
+                "stylesheet {} of theme {} does not exist",
+                css,
+                manifest.name
+            );
+        }
+        let templates = manifest.templates.map(|t| dir.join(t));
+        if let Some(templates) = &templates {
+            anyhow::ensure!(
+                templates.is_dir(),
+                "template directory {} of theme {} does not exist",
+                templates.display(),
+                manifest.name
+            );
+        }
+        Ok(Theme {
+            name: manifest.name,
+            source: Source::External(dir.to_path_buf()),
+            use_css: manifest.css,
+            templates,
+        })
+    }
+}
+
+/// File describing an external theme directory
+pub const MANIFEST: &str = "theme.toml";
+
+/// Contents of `theme.toml`; every other file in the theme directory is copied to the output
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    name: String,
+    /// Stylesheets linked from every page, relative to the theme directory
+    css: Vec<String>,
+    /// Directory of templates replacing the built-in ones, relative to the theme directory
+    templates: Option<std::path::PathBuf>,
+}
+
+/// Copy the files below `from` into `to`, apart from those in `skip`
+pub fn copy_dir(
+    from: &std::path::Path,
+    to: &std::path::Path,
+    skip: &[std::path::PathBuf],
+) -> Result<()> {
+    for entry in from
+        .read_dir()
+        .with_context(|| format!("reading {}", from.display()))?
+    {
+        let path = entry?.path();
+        if skip.contains(&path) {
+            continue;
+        }
+        let target = to.join(path.file_name().unwrap());
+        if path.is_dir() {
+            std::fs::create_dir_all(&target)
+                .with_context(|| format!("creating {}", target.display()))?;
+            copy_dir(&path, &target, skip)?;
+        } else {
+            std::fs::copy(&path, &target)
+                .with_context(|| format!("copying {} to {}", path.display(), target.display()))?;
+        }
     }
+    Ok(())
+}
+
+const FONT_DIR: include_dir::Dir = include_dir::include_dir!("$CARGO_MANIFEST_DIR/font");
+
+/// Stylesheet declaring the bundled fonts, linked instead of Google Fonts in offline mode
+pub const FONT_CSS: &str = "fonts.css";
+
+/// One `@font-face` rule of [`FONT_CSS`]
+struct Face {
+    family: &'static str,
+    style: &'static str,
+    weight: u32,
+    /// Locally installed names tried before the bundled file
+    local: &'static [&'static str],
+    /// File in `font/`, if the face is bundled
+    file: Option<&'static str>,
 }
 
-const THEME_MCSS: Theme = Theme {
-    dir: &include_dir::include_dir!("$CARGO_MANIFEST_DIR/contrib/m.css/css"),
-    use_css: &[],
-};
+/// The faces m.css asks for. Source Sans Pro is not bundled yet, so without a local copy the
+/// browser falls back to the theme's sans-serif stack.
+const FACES: &[Face] = &[
+    Face {
+        family: "Source Sans Pro",
+        style: "normal",
+        weight: 400,
+        local: &["Source Sans Pro", "SourceSansPro-Regular"],
+        file: None,
+    },
+    Face {
+        family: "Source Sans Pro",
+        style: "italic",
+        weight: 400,
+        local: &["Source Sans Pro Italic", "SourceSansPro-It"],
+        file: None,
+    },
+    Face {
+        family: "Source Sans Pro",
+        style: "normal",
+        weight: 600,
+        local: &["Source Sans Pro Semibold", "SourceSansPro-Semibold"],
+        file: None,
+    },
+    Face {
+        family: "Source Code Pro",
+        style: "normal",
+        weight: 400,
+        local: &["Source Code Pro", "SourceCodePro-Regular"],
+        file: Some("SourceCodePro-Regular.woff2"),
+    },
+    Face {
+        family: "Source Code Pro",
+        style: "italic",
+        weight: 400,
+        local: &["Source Code Pro Italic", "SourceCodePro-It"],
+        file: Some("SourceCodePro-It.woff2"),
+    },
+    Face {
+        family: "Source Code Pro",
+        style: "normal",
+        weight: 600,
+        local: &["Source Code Pro Semibold", "SourceCodePro-Semibold"],
+        file: Some("SourceCodePro-Semibold.woff2"),
+    },
+];
 
-pub const THEME_MCSS_DARK: Theme = Theme {
-    use_css: &["m-dark.css"],
-    ..THEME_MCSS
-};
+fn font_css() -> String {
+    FACES
+        .iter()
+        .map(|face| {
+            let src = face
+                .local
+                .iter()
+                .map(|name| format!("local('{}')", name))
+                .chain(
+                    face.file
+                        .map(|file| format!("url('fonts/{}') format('woff2')", file)),
+                )
+                .collect::<Vec<_>>()
+                .join(", ");
+            format!(
+                "@font-face {{\n  font-family: '{}';\n  font-style: {};\n  font-weight: {};\n  src: {};\n}}\n",
+                face.family, face.style, face.weight, src
+            )
+        })
+        .collect()
+}
 
-pub const THEME_DEFAULT: Theme = THEME_MCSS_DARK;
+/// Every reference to an external resource in the HTML and CSS files below `dir`, as
+/// `file: url` with `file` relative to `dir`
+///
+/// Stylesheets, scripts, images, fonts and CSS imports are checked; plain `<a href>` links are
+/// not, since following them is up to the reader.
+pub fn external_urls(dir: &std::path::Path) -> Result<Vec<String>> {
+    fn walk(root: &std::path::Path, dir: &std::path::Path, out: &mut Vec<String>) -> Result<()> {
+        let mut entries = dir
+            .read_dir()
+            .with_context(|| format!("reading {}", dir.display()))?
+            .map(|e| e.map(|e| e.path()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        entries.sort();
+        for path in entries {
+            if path.is_dir() {
+                walk(root, &path, out)?;
+                continue;
+            }
+            match path.extension().and_then(|e| e.to_str()) {
+                Some("html") | Some("css") => {}
+                _ => continue,
+            }
+            let text = std::fs::read_to_string(&path)
+                .with_context(|| format!("reading {}", path.display()))?;
+            for url in resource_urls(&text) {
+                if is_external(url) {
+                    let file = path.strip_prefix(root).unwrap_or(&path);
+                    out.push(format!("{}: {}", file.display(), url));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    let mut out = vec![];
+    walk(dir, dir, &mut out)?;
+    Ok(out)
+}
+
+/// URLs a browser would fetch while loading `text`
+fn resource_urls(text: &str) -> Vec<&str> {
+    let mut out = vec![];
+    for marker in ["src=", "href=", "url(", "@import"] {
+        for (at, _) in text.match_indices(marker) {
+            if marker == "href=" {
+                let tag = &text[text[..at].rfind('<').unwrap_or(0)..at];
+                if tag.starts_with("<a ") || tag.starts_with("<a\n") {
+                    continue;
+                }
+            }
+            let rest = text[at + marker.len()..].trim_start();
+            if marker == "@import" && rest.starts_with("url(") {
+                // Found by the `url(` marker already
+                continue;
+            }
+            let rest = rest.trim_start_matches(['"', '\'']);
+            let end = rest
+                .find(['"', '\'', ')', ' ', '>', '\n'])
+                .unwrap_or(rest.len());
+            out.push(&rest[..end]);
+        }
+    }
+    out
+}
+
+fn is_external(url: &str) -> bool {
+    let url = url.to_ascii_lowercase();
+    url.starts_with("//") || url.starts_with("http:") || url.starts_with("https:")
+}
+
+const MCSS_DIR: include_dir::Dir =
+    include_dir::include_dir!("$CARGO_MANIFEST_DIR/contrib/m.css/css");
+
+/// Embedded m.css variants by name, with the stylesheets each one links
+pub const THEMES: &[(&str, &[&str])] = &[
+    ("m-dark", &["m-dark.css"]),
+    ("m-light", &["m-light.css"]),
+    ("m-dark-compiled", &["m-dark.compiled.css"]),
+    ("m-light-compiled", &["m-light.compiled.css"]),
+];
+
+pub const THEME_DEFAULT_NAME: &str = "m-dark";
+
+/// The theme selected by the configuration: an external directory if given, else an embedded
+/// theme by name
+pub fn theme(config: &crate::config::Theme) -> Result<Theme> {
+    if let Some(dir) = &config.path {
+        return Theme::load(dir);
+    }
+    let (name, css) = THEMES
+        .iter()
+        .find(|(name, _)| *name == config.name)
+        .with_context(|| {
+            format!(
+                "unknown theme {} (available: {})",
+                config.name,
+                THEMES
+                    .iter()
+                    .map(|(name, _)| *name)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        })?;
+    Ok(Theme {
+        name: name.to_string(),
+        source: Source::Embedded(&MCSS_DIR),
+        use_css: css.iter().map(|s| s.to_string()).collect(),
+        templates: None,
+    })
+}
+
+/// File name of a copied asset: the source file name with a hash of its contents inserted, so
+/// browsers fetch it again whenever it changes
+pub fn hashed_name(source: &std::path::Path, content: &[u8]) -> String {
+    // 64-bit FNV-1a, which unlike `DefaultHasher` is stable across Rust releases
+    let hash = content.iter().fold(0xcbf2_9ce4_8422_2325_u64, |hash, b| {
+        (hash ^ u64::from(*b)).wrapping_mul(0x0000_0100_0000_01b3)
+    });
+    let stem = source.file_stem().unwrap_or_default().to_string_lossy();
+    match source.extension() {
+        Some(ext) => format!("{}.{:016x}.{}", stem, hash, ext.to_string_lossy()),
+        None => format!("{}.{:016x}", stem, hash),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn embedded() -> Result<()> {
+        for (name, _) in THEMES {
+            let theme = theme(&crate::config::Theme {
+                name: name.to_string(),
+                path: None,
+            })?;
+            for css in theme.css_files() {
+                assert!(MCSS_DIR.get_file(&css).is_some(), "{} missing", css);
+            }
+        }
+        assert!(theme(&crate::config::Theme {
+            name: "nope".to_string(),
+            path: None,
+        })
+        .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn external() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir_all(dir.path().join("fonts"))?;
+        std::fs::create_dir_all(dir.path().join("templates"))?;
+        std::fs::write(
+            dir.path().join(MANIFEST),
+            "name = \"acme\"\ncss = [\"acme.css\"]\ntemplates = \"templates\"\n",
+        )?;
+        std::fs::write(dir.path().join("acme.css"), "body {}")?;
+        std::fs::write(dir.path().join("fonts/acme.woff2"), "")?;
+        std::fs::write(dir.path().join("templates/page_header.html"), "")?;
+        let theme = theme(&crate::config::Theme {
+            path: Some(dir.path().to_path_buf()),
+            ..Default::default()
+        })?;
+        assert_eq!(theme.name, "acme");
+        assert_eq!(theme.css_files(), vec!["acme.css"]);
+
+        let out = tempfile::tempdir()?;
+        theme.store(out.path(), false)?;
+        assert!(out.path().join("acme.css").is_file());
+        assert!(out.path().join("fonts/acme.woff2").is_file());
+        assert!(!out.path().join(MANIFEST).exists());
+        assert!(!out.path().join("templates").exists());
+
+        std::fs::write(
+            dir.path().join(MANIFEST),
+            "name = \"acme\"\ncss = [\"gone.css\"]\n",
+        )?;
+        assert!(Theme::load(dir.path()).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn offline() -> Result<()> {
+        let out = tempfile::tempdir()?;
+        theme(&Default::default())?.store(out.path(), true)?;
+        assert!(out
+            .path()
+            .join("fonts/SourceCodePro-Regular.woff2")
+            .is_file());
+        let css = std::fs::read_to_string(out.path().join(FONT_CSS))?;
+        assert!(css.contains("url('fonts/SourceCodePro-It.woff2')"));
+        for face in FACES.iter().filter_map(|f| f.file) {
+            assert!(FONT_DIR.get_file(face).is_some(), "{} missing", face);
+        }
+        assert!(external_urls(out.path())?.is_empty());
+
+        std::fs::write(
+            out.path().join("page.html"),
+            "<link rel=\"stylesheet\" href=\"https://fonts.example/x.css\" />\n\
+             <a href=\"https://example.com\">fine</a><img src='//cdn.example/i.png'>",
+        )?;
+        std::fs::write(
+            out.path().join("extra.css"),
+            "@import url(\"http://cdn.example/a.css\");",
+        )?;
+        assert_eq!(
+            external_urls(out.path())?
+                .iter()
+                .map(|u| u.rsplit(": ").next().unwrap())
+                .collect::<Vec<_>>(),
+            vec![
+                "http://cdn.example/a.css",
+                "//cdn.example/i.png",
+                "https://fonts.example/x.css"
+            ]
+        );
+
+        Ok(())
+    }
+}
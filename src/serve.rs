@@ -0,0 +1,148 @@
+//! Minimal HTTP server used by `blueprint serve` to preview a build
+
+use anyhow::{Context, Result};
+use std::io::{BufRead, Write};
+
+/// Serve `root` on `address` forever, calling `refresh` before every page request so the caller
+/// can rebuild stale output
+pub fn serve<F>(address: &str, root: &std::path::Path, mut refresh: F) -> Result<()>
+where
+    F: FnMut() -> Result<()>,
+{
+    let listener =
+        std::net::TcpListener::bind(address).with_context(|| format!("binding {}", address))?;
+    for stream in listener.incoming() {
+        if let Err(err) = stream
+            .map_err(anyhow::Error::from)
+            .and_then(|mut s| handle(&mut s, root, &mut refresh))
+        {
+            eprintln!("{:#}", err);
+        }
+    }
+    Ok(())
+}
+
+fn handle<F>(
+    stream: &mut std::net::TcpStream,
+    root: &std::path::Path,
+    refresh: &mut F,
+) -> Result<()>
+where
+    F: FnMut() -> Result<()>,
+{
+    let mut request = String::new();
+    std::io::BufReader::new(&*stream).read_line(&mut request)?;
+    let mut parts = request.split_whitespace();
+    let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    if method != "GET" {
+        return respond(
+            stream,
+            "405 Method Not Allowed",
+            "text/plain",
+            b"method not allowed",
+        );
+    }
+    let file = match resolve(target) {
+        Some(file) => root.join(file),
+        None => return respond(stream, "404 Not Found", "text/plain", b"not found"),
+    };
+    if file.extension() == Some(std::ffi::OsStr::new("html")) {
+        if let Err(err) = refresh() {
+            eprintln!("{:#}", err);
+        }
+    }
+    match std::fs::read(&file) {
+        Ok(body) => respond(stream, "200 OK", content_type(&file), &body),
+        Err(_) => respond(stream, "404 Not Found", "text/plain", b"not found"),
+    }
+}
+
+/// Map a request target onto a relative file path, refusing anything that escapes the root
+fn resolve(target: &str) -> Option<std::path::PathBuf> {
+    let path = percent_decode(target.split(['?', '#']).next()?)?;
+    let path = path.trim_start_matches('/');
+    let path = if path.is_empty() { "pages.html" } else { path };
+    let mut out = std::path::PathBuf::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => return None,
+            p if p.contains('\\') => return None,
+            p => out.push(p),
+        }
+    }
+    Some(out)
+}
+
+/// Decode `%XX` escapes, failing on malformed escapes or a result that is not UTF-8
+fn percent_decode(text: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = tail
+                .get(..2)
+                .filter(|h| h.iter().all(u8::is_ascii_hexdigit))?;
+            bytes.push(u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+fn content_type(file: &std::path::Path) -> &'static str {
+    match file.extension().and_then(|e| e.to_str()) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("css") => "text/css",
+        Some("js") => "text/javascript",
+        Some("json") => "application/json",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("ttf") => "font/ttf",
+        _ => "application/octet-stream",
+    }
+}
+
+fn respond(
+    stream: &mut std::net::TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+) -> Result<()> {
+    write!(
+        stream,
+        "HTTP/1.0 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths() {
+        assert_eq!(resolve("/"), Some("pages.html".into()));
+        assert_eq!(
+            resolve("/theme/m-dark.css?v=1"),
+            Some("theme/m-dark.css".into())
+        );
+        assert_eq!(resolve("/../secret"), None);
+        assert_eq!(resolve("/theme/../../secret"), None);
+        assert_eq!(resolve("/my%20page.html"), Some("my page.html".into()));
+        assert_eq!(resolve("/%2e%2e/secret"), None);
+        assert_eq!(resolve("/theme%2F..%2F..%2Fsecret"), None);
+        assert_eq!(resolve("/a%2"), None);
+    }
+}
@@ -0,0 +1,184 @@
+//! Discovery of blueprint source files below an input directory
+
+use anyhow::{Context, Result};
+
+/// Patterns selecting source files when none are given
+pub const INCLUDE_DEFAULT: &[&str] = &["*.bp"];
+
+/// Walks an input directory, following symlinks, and selects files by glob
+///
+/// Patterns are matched against paths relative to the input directory with `/` separators, and
+/// `*` also matches across directories, so `*.bp` selects every blueprint in the tree. A directory
+/// matching an exclude pattern is skipped entirely.
+pub struct Finder {
+    root: std::path::PathBuf,
+    include: Vec<glob::Pattern>,
+    exclude: Vec<glob::Pattern>,
+}
+
+impl Finder {
+    pub fn new<S: AsRef<str>>(
+        root: &std::path::Path,
+        include: &[S],
+        exclude: &[S],
+    ) -> Result<Finder> {
+        Ok(Finder {
+            root: root.to_path_buf(),
+            include: if include.is_empty() {
+                compile(INCLUDE_DEFAULT)?
+            } else {
+                compile(include)?
+            },
+            exclude: compile(exclude)?,
+        })
+    }
+
+    pub fn root(&self) -> &std::path::Path {
+        &self.root
+    }
+
+    /// Every selected file, in a stable order
+    pub fn find(&self) -> Result<Vec<std::path::PathBuf>> {
+        anyhow::ensure!(
+            self.root.is_dir(),
+            "input directory {} does not exist",
+            self.root.display()
+        );
+        let mut out = vec![];
+        let mut visited = std::collections::HashSet::new();
+        self.walk(&self.root, &mut visited, &mut out)?;
+        out.sort();
+        Ok(out)
+    }
+
+    fn walk(
+        &self,
+        dir: &std::path::Path,
+        visited: &mut std::collections::HashSet<std::path::PathBuf>,
+        out: &mut Vec<std::path::PathBuf>,
+    ) -> Result<()> {
+        // A directory reachable twice through symlinks is only walked the first time
+        if !visited.insert(dir.canonicalize()?) {
+            return Ok(());
+        }
+        for entry in dir
+            .read_dir()
+            .with_context(|| format!("reading {}", dir.display()))?
+        {
+            let path = entry?.path();
+            let relative = relative_name(&self.root, &path);
+            if self.exclude.iter().any(|p| matches(p, &relative)) {
+                continue;
+            }
+            match std::fs::metadata(&path) {
+                Ok(meta) if meta.is_dir() => self.walk(&path, visited, out)?,
+                Ok(_) if self.include.iter().any(|p| matches(p, &relative)) => out.push(path),
+                Ok(_) => {}
+                Err(_) if self.include.iter().any(|p| matches(p, &relative)) => {
+                    anyhow::bail!("broken symlink {}", path.display())
+                }
+                Err(_) => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+fn compile<S: AsRef<str>>(patterns: &[S]) -> Result<Vec<glob::Pattern>> {
+    patterns
+        .iter()
+        .map(|p| {
+            glob::Pattern::new(p.as_ref())
+                .with_context(|| format!("invalid pattern {}", p.as_ref()))
+        })
+        .collect()
+}
+
+fn matches(pattern: &glob::Pattern, relative: &str) -> bool {
+    pattern.matches_with(
+        relative,
+        glob::MatchOptions {
+            case_sensitive: true,
+            require_literal_separator: false,
+            require_literal_leading_dot: false,
+        },
+    )
+}
+
+/// Path of `file` relative to `root`, joined with `/` on every platform
+pub fn relative_name(root: &std::path::Path, file: &std::path::Path) -> String {
+    file.strip_prefix(root)
+        .unwrap_or(file)
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_tree(name: &str) -> tempfile::TempDir {
+        let dir = tempfile::Builder::new()
+            .prefix(&format!("blueprint-source-{}-", name))
+            .tempdir()
+            .unwrap();
+        for (file, text) in [
+            ("top.bp", "# top"),
+            ("notes.txt", "not a blueprint"),
+            ("net/api.bp", "# net"),
+            ("ui/api.bp", "# ui"),
+            ("drafts/wip.bp", "# wip"),
+        ] {
+            let path = dir.path().join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, text).unwrap();
+        }
+        dir
+    }
+
+    fn names(finder: &Finder) -> Vec<String> {
+        finder
+            .find()
+            .unwrap()
+            .iter()
+            .map(|f| relative_name(finder.root(), f))
+            .collect()
+    }
+
+    #[test]
+    fn recursive() {
+        let dir = make_tree("recursive");
+        let none: &[&str] = &[];
+        assert_eq!(
+            names(&Finder::new(dir.path(), none, none).unwrap()),
+            vec!["drafts/wip.bp", "net/api.bp", "top.bp", "ui/api.bp"]
+        );
+        assert_eq!(
+            names(&Finder::new(dir.path(), &["net/*"], &[]).unwrap()),
+            vec!["net/api.bp"]
+        );
+        assert_eq!(
+            names(&Finder::new(dir.path(), none, &["drafts", "top.bp"]).unwrap()),
+            vec!["net/api.bp", "ui/api.bp"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks() {
+        let dir = make_tree("symlinks");
+        std::os::unix::fs::symlink(dir.path(), dir.path().join("net/loop")).ok();
+        std::os::unix::fs::symlink(dir.path().join("ui/api.bp"), dir.path().join("alias.bp")).ok();
+        let none: &[&str] = &[];
+        assert_eq!(
+            names(&Finder::new(dir.path(), none, &["drafts"]).unwrap()),
+            vec!["alias.bp", "net/api.bp", "top.bp", "ui/api.bp"]
+        );
+
+        std::os::unix::fs::symlink(dir.path().join("missing.bp"), dir.path().join("broken.bp"))
+            .ok();
+        assert!(Finder::new(dir.path(), none, none).unwrap().find().is_err());
+    }
+}
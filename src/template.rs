@@ -1,5 +1,5 @@
 use crate::markup::*;
-use anyhow::Result;
+use anyhow::{Context as _, Result};
 
 const TEMPLATE_DIR: include_dir::Dir = include_dir::include_dir!("$CARGO_MANIFEST_DIR/template");
 
@@ -8,37 +8,61 @@ pub struct Page {
     pub file: std::path::PathBuf,
     pub title: String,
     pub content: Blueprint,
+    /// Where the content appears in context, for pages taken out of a larger blueprint
+    pub origin: Option<Origin>,
+    /// Properties listed above the content, such as the attributes of a requirement
+    pub attributes: std::collections::BTreeMap<String, String>,
+    /// Links to and from the requirement a page shows
+    pub relations: Vec<Related>,
+}
+
+/// One end of a relation link, as listed on a requirement page
+#[derive(Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct Related {
+    pub label: String,
+    pub color: String,
+    pub directed: bool,
+    /// Whether the link points at the page's requirement rather than away from it
+    pub incoming: bool,
+    pub title: String,
+    pub link: String,
+}
+
+/// A section of another page, linked to by its anchor
+#[derive(Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct Origin {
+    pub title: String,
+    pub link: String,
 }
 
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct Context {
+    pub config: crate::config::Config,
+    /// Name of the theme in use, so overridden templates can adapt to it
+    pub theme: String,
     pub css: Vec<String>,
     pub page: Page,
 }
 
 pub struct Engine {
     tera: tera::Tera,
+    config: crate::config::Config,
+    theme: crate::resource::Theme,
 }
 
 impl Engine {
-    pub fn new() -> Result<Engine> {
+    pub fn new(config: &crate::config::Config) -> Result<Engine> {
         let mut tera = tera::Tera::default();
+        let tag_config = config.clone();
         tera.register_function(
             "tag_class",
-            |args: &std::collections::HashMap<String, tera::Value>| -> tera::Result<tera::Value> {
+            move |args: &std::collections::HashMap<String, tera::Value>| -> tera::Result<tera::Value> {
                 if let Some(tag) = args.get("tag") {
                     if let Ok(tag) = tera::from_value::<Tag>(tag.clone()) {
-                        match tag.category {
-                            TagCategory::Simple => {
-                                Ok(tera::to_value(" class=\"m-label m-flat m-default\"")?)
-                            }
-                            TagCategory::Requires => {
-                                Ok(tera::to_value(" class=\"m-label m-warning\"")?)
-                            }
-                            TagCategory::Satisfies => {
-                                Ok(tera::to_value(" class=\"m-label m-success\"")?)
-                            }
-                        }
+                        Ok(tera::to_value(format!(
+                            " class=\"{}\"",
+                            escape(&tag_config.tag_class(&tag))
+                        ))?)
                     } else {
                         Err("'tag' is not a tag".into())
                     }
@@ -47,12 +71,13 @@ impl Engine {
                 }
             },
         );
+        let output = config.output.clone();
         tera.register_function(
             "tag_link",
-            |args: &std::collections::HashMap<String, tera::Value>| -> tera::Result<tera::Value> {
+            move |args: &std::collections::HashMap<String, tera::Value>| -> tera::Result<tera::Value> {
                 if let Some(tag) = args.get("tag") {
                     if let Ok(tag) = tera::from_value::<Tag>(tag.clone()) {
-                        Ok(tera::to_value(tag.file_name())?)
+                        Ok(tera::to_value(output.tag_file(&tag))?)
                     } else {
                         Err("'tag' is not a tag".into())
                     }
@@ -61,60 +86,207 @@ impl Engine {
                 }
             },
         );
+        let output = config.output.clone();
+        tera.register_function(
+            "requirement_link",
+            move |args: &std::collections::HashMap<String, tera::Value>| -> tera::Result<tera::Value> {
+                match args.get("name").and_then(tera::Value::as_str) {
+                    Some(name) => Ok(tera::to_value(output.requirement_file(name))?),
+                    None => Err("'name' argument missing".into()),
+                }
+            },
+        );
         tera.register_function(
             "span_class",
             |args: &std::collections::HashMap<String, tera::Value>| -> tera::Result<tera::Value> {
-                if let Some(span) = args.get("span") {
-                    match tera::from_value::<Span>(span.clone()) {
-                        Ok(Span::Plain(_)) => Ok(tera::to_value("")?),
-                        Ok(Span::Bold(_)) => Ok(tera::to_value(" class=\"m-text m-strong\"")?),
-                        Ok(Span::Italic(_)) => Ok(tera::to_value(" class=\"m-text m-em\"")?),
-                        Ok(Span::Strikethrough(_)) => Ok(tera::to_value(" class=\"m-text m-s\"")?),
-                        Ok(Span::Link(_)) => Ok(tera::to_value("")?),
-                        Err(_) => Err("'span' is not a span".into()),
+                match span_arg(args)? {
+                    Span::Plain(_) | Span::Link(..) | Span::Code(_) | Span::Ref(_) => {
+                        Ok(tera::to_value("")?)
                     }
-                } else {
-                    Err("'span' argument missing".into())
+                    Span::Bold(_) => Ok(tera::to_value(" class=\"m-text m-strong\"")?),
+                    Span::Italic(_) => Ok(tera::to_value(" class=\"m-text m-em\"")?),
+                    Span::Strikethrough(_) => Ok(tera::to_value(" class=\"m-text m-s\"")?),
+                }
+            },
+        );
+        tera.register_function(
+            "span_children",
+            |args: &std::collections::HashMap<String, tera::Value>| -> tera::Result<tera::Value> {
+                Ok(tera::to_value(span_arg(args)?.children())?)
+            },
+        );
+        let output = config.output.clone();
+        tera.register_function(
+            "span_link",
+            move |args: &std::collections::HashMap<String, tera::Value>| -> tera::Result<tera::Value> {
+                match span_arg(args)? {
+                    Span::Link(_, link) if output.allows_link(&link) => {
+                        Ok(tera::to_value(escape(&link))?)
+                    }
+                    _ => Ok(tera::to_value("")?),
                 }
             },
         );
+        tera.register_function(
+            "section_progress",
+            |args: &std::collections::HashMap<String, tera::Value>| -> tera::Result<tera::Value> {
+                let section = args.get("section").ok_or("'section' argument missing")?;
+                let section = tera::from_value::<Section>(section.clone())?;
+                Ok(tera::to_value(section.progress())?)
+            },
+        );
+        tera.register_function(
+            "highlight",
+            |args: &std::collections::HashMap<String, tera::Value>| -> tera::Result<tera::Value> {
+                let language = args
+                    .get("language")
+                    .and_then(tera::Value::as_str)
+                    .unwrap_or("");
+                let code = args
+                    .get("code")
+                    .and_then(tera::Value::as_str)
+                    .ok_or("'code' argument missing")?;
+                crate::highlight::highlight(language, code)
+                    .map(tera::Value::String)
+                    .map_err(|err| err.to_string().into())
+            },
+        );
         tera.register_filter(
             "span_text",
             |val: &tera::Value,
              _args: &std::collections::HashMap<String, tera::Value>|
              -> tera::Result<tera::Value> {
-                match tera::from_value::<Span>(val.clone()) {
-                    Ok(Span::Plain(s)) => Ok(tera::to_value(s)?),
-                    Ok(Span::Bold(s)) => Ok(tera::to_value(s)?),
-                    Ok(Span::Italic(s)) => Ok(tera::to_value(s)?),
-                    Ok(Span::Strikethrough(s)) => Ok(tera::to_value(s)?),
-                    Ok(Span::Link(s)) => {
-                        Ok(tera::to_value(format!("<a href=\"{}\">{}</a>", s.1, s.0))?)
-                    }
-                    Err(_) => Err("not a span".into()),
+                match to_span(val) {
+                    Some(span) => Ok(tera::to_value(escape(&span.text()))?),
+                    None => Err("not a span".into()),
                 }
             },
         );
-        let templates = TEMPLATE_DIR
-            .files()
-            .map(|f| {
-                (
-                    f.path().file_name().unwrap().to_str().unwrap(),
-                    f.contents_utf8().unwrap(),
-                )
-            })
+        let theme = crate::resource::theme(&config.theme)?;
+        let dirs = theme
+            .templates()
+            .into_iter()
+            .chain(config.templates.path.as_deref())
             .collect::<Vec<_>>();
-        tera.add_raw_templates(templates)?;
+        tera.add_raw_templates(templates(&dirs)?).with_context(|| {
+            let dirs = dirs
+                .iter()
+                .map(|d| d.display().to_string())
+                .collect::<Vec<_>>();
+            if dirs.is_empty() {
+                "loading templates".to_string()
+            } else {
+                format!("loading templates with overrides from {}", dirs.join(", "))
+            }
+        })?;
+
+        Ok(Engine {
+            tera,
+            config: config.clone(),
+            theme,
+        })
+    }
 
-        Ok(Engine { tera })
+    pub fn theme(&self) -> &crate::resource::Theme {
+        &self.theme
     }
 
     pub fn render(&self, input: &Page) -> Result<String> {
-        let mut ctx = tera::Context::new();
-        ctx.insert("page", input);
-        ctx.insert("css", &crate::resource::THEME_DEFAULT.css_files());
-        Ok(self.tera.render("page.html", &ctx)?)
+        let ctx = tera::Context::from_serialize(Context {
+            config: self.config.clone(),
+            theme: self.theme.name.clone(),
+            css: self.theme.css_files(),
+            page: input.clone(),
+        })?;
+        self.tera
+            .render("page.html", &ctx)
+            .context("rendering template page.html")
+    }
+
+    /// Render one of the index templates, exposing `index` alongside the usual page context
+    pub fn render_index<T: serde::Serialize>(
+        &self,
+        template: &str,
+        title: &str,
+        index: &T,
+    ) -> Result<String> {
+        let mut ctx = tera::Context::from_serialize(Context {
+            config: self.config.clone(),
+            theme: self.theme.name.clone(),
+            css: self.theme.css_files(),
+            page: Page {
+                file: template.into(),
+                title: title.to_string(),
+                ..Default::default()
+            },
+        })?;
+        ctx.insert("index", index);
+        self.tera
+            .render(template, &ctx)
+            .with_context(|| format!("rendering template {}", template))
+    }
+}
+
+/// A span as serialized in a template context, either located at the top of a paragraph or nested
+/// inside another span
+fn to_span(value: &tera::Value) -> Option<Span> {
+    tera::from_value::<Located<Span>>(value.clone())
+        .map(|s| s.node)
+        .or_else(|_| tera::from_value::<Span>(value.clone()))
+        .ok()
+}
+
+fn span_arg(args: &std::collections::HashMap<String, tera::Value>) -> tera::Result<Span> {
+    let span = args.get("span").ok_or("'span' argument missing")?;
+    to_span(span).ok_or_else(|| "'span' is not a span".into())
+}
+
+/// Escape text for use in HTML content and quoted attribute values
+pub fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// The embedded templates by file name, with the files in each of `dirs` in turn replacing or
+/// adding to them
+fn templates(dirs: &[&std::path::Path]) -> Result<std::collections::BTreeMap<String, String>> {
+    let mut out = TEMPLATE_DIR
+        .files()
+        .map(|f| {
+            (
+                f.path().file_name().unwrap().to_string_lossy().to_string(),
+                f.contents_utf8().unwrap().to_string(),
+            )
+        })
+        .collect::<std::collections::BTreeMap<_, _>>();
+    for dir in dirs {
+        for entry in dir
+            .read_dir()
+            .with_context(|| format!("reading template directory {}", dir.display()))?
+        {
+            let path = entry?.path();
+            if !path.is_file() {
+                continue;
+            }
+            let text = std::fs::read_to_string(&path)
+                .with_context(|| format!("reading template {}", path.display()))?;
+            out.insert(
+                path.file_name().unwrap().to_string_lossy().to_string(),
+                text,
+            );
+        }
     }
+    Ok(out)
 }
 
 #[cfg(test)]
@@ -131,10 +303,309 @@ mod tests {
     #[test]
     fn page() -> Result<()> {
         assert_eq!(
-            Engine::new()?.render(&make_page())?,
+            Engine::new(&Default::default())?.render(&make_page())?,
             include_str!("../test/template/page.html")
         );
 
         Ok(())
     }
+
+    #[test]
+    fn index() -> Result<()> {
+        let out = Engine::new(&Default::default())?.render_index(
+            "requirements.html",
+            "Requirements",
+            &vec![crate::model::RequirementEntry {
+                name: "r1".to_string(),
+                file: "req_r1.html".to_string(),
+                satisfied: true,
+                progress: Progress { done: 1, total: 4 },
+                ..Default::default()
+            }],
+        )?;
+        assert!(out.contains("<title>Requirements</title>"));
+        assert!(out.contains("href=\"req_r1.html\""));
+        assert!(
+            out.contains("<progress class=\"m-progress\" max=\"4\" value=\"1\"></progress> 1/4")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn config() -> Result<()> {
+        let mut config = crate::config::Config::default();
+        config.project.name = "Acme".to_string();
+        config.output.requirement_prefix = "r-".to_string();
+        config.tags.pages = false;
+        let out = Engine::new(&config)?.render_index(
+            "trace.html",
+            "Traceability",
+            &crate::model::TraceReport {
+                matrix: crate::trace::Matrix {
+                    requirements: vec!["r1".to_string()],
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        )?;
+        assert!(out.contains(">Acme</a"));
+        assert!(out.contains("href=\"r-r1.html\""));
+        assert!(!out.contains("tags.html"));
+
+        config.theme.name = "nope".to_string();
+        assert!(Engine::new(&config).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn overrides() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("page_header.html"), "<p>Acme Corp</p>")?;
+        let mut config = crate::config::Config::default();
+        config.templates.path = Some(dir.path().to_path_buf());
+        let out = Engine::new(&config)?.render(&make_page())?;
+        assert!(out.contains("<p>Acme Corp</p>"));
+        assert!(out.contains("<title>test</title>"));
+
+        std::fs::write(dir.path().join("page_header.html"), "{{ nope }}")?;
+        let err = Engine::new(&config)?.render(&make_page()).unwrap_err();
+        assert!(format!("{:#}", err).contains("'page_header.html'"));
+
+        std::fs::write(dir.path().join("macros.html"), "{% macro broken( %}")?;
+        let err = Engine::new(&config).err().unwrap();
+        assert!(format!("{:#}", err).contains("macros.html"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn theme() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir_all(dir.path().join("templates"))?;
+        std::fs::write(
+            dir.path().join(crate::resource::MANIFEST),
+            "name = \"acme\"\ncss = [\"acme.css\"]\ntemplates = \"templates\"\n",
+        )?;
+        std::fs::write(dir.path().join("acme.css"), "")?;
+        std::fs::write(
+            dir.path().join("templates/page_header.html"),
+            "{{ theme }} header",
+        )?;
+        let mut config = crate::config::Config::default();
+        config.theme.path = Some(dir.path().to_path_buf());
+        let out = Engine::new(&config)?.render(&make_page())?;
+        assert!(out.contains("acme header"));
+        assert!(out.contains("href=\"theme/acme.css\""));
+
+        Ok(())
+    }
+
+    #[test]
+    fn escaping() -> Result<()> {
+        let page = Page {
+            title: "<t>".to_string(),
+            content: Blueprint::parse(
+                "",
+                "# a <b>\n\n<script>\"x\" [q](a\"onclick=\"x) [j](javascript:alert(1))\n\n<<<\n<hr class=\"raw\">\n>>>\n",
+            )?,
+            ..Default::default()
+        };
+        let out = Engine::new(&Default::default())?.render(&page)?;
+        assert!(out.contains("<title>&lt;t&gt;</title>"));
+        assert!(out.contains("a &lt;b&gt;"));
+        assert!(out.contains("&lt;script&gt;&quot;x&quot;"));
+        assert!(out.contains("<a href=\"a&quot;onclick=&quot;x\">q</a>"));
+        assert!(!out.contains("javascript:"));
+        assert!(out.contains("<hr class=\"raw\">"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn nested() -> Result<()> {
+        let page = Page {
+            content: Blueprint::parse("", "# a\n\n*b /c/ [~d~ e](f.html)* [g](javascript:x)\n")?,
+            ..Default::default()
+        };
+        let out = Engine::new(&Default::default())?.render(&page)?;
+        assert!(out.contains(
+            "<span class=\"m-text m-strong\">b <span class=\"m-text m-em\">c</span> \
+             <a href=\"f.html\"><span class=\"m-text m-s\">d</span> e</a></span> g"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn code() -> Result<()> {
+        let page = Page {
+            content: Blueprint::parse("", "# a\n\nsee `<b>`\n\n```rust\nfn c() {}\n```\n")?,
+            ..Default::default()
+        };
+        let out = Engine::new(&Default::default())?.render(&page)?;
+        assert!(out.contains("see <code>&lt;b&gt;</code>"));
+        assert!(out.contains("<pre class=\"m-code\"><span class=\"k\">fn</span>"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn list() -> Result<()> {
+        let page = Page {
+            content: Blueprint::parse("", "# a\n\n- b\n  1. *c*\n- d\n")?,
+            ..Default::default()
+        };
+        let out = Engine::new(&Default::default())?.render(&page)?;
+        assert!(out.contains(
+            "<ul><li>b<ol><li><span class=\"m-text m-strong\">c</span></li></ol></li><li>d</li></ul>"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn tasks() -> Result<()> {
+        let page = Page {
+            content: Blueprint::parse("", "# a\n\n## b\n\n[x] c\n[ ] d\n")?,
+            ..Default::default()
+        };
+        let out = Engine::new(&Default::default())?.render(&page)?;
+        assert_eq!(out.matches("max=\"2\" value=\"1\"").count(), 2);
+        assert!(out.contains("<li><input type=\"checkbox\" disabled checked> c</li>"));
+        assert!(out.contains("<li><input type=\"checkbox\" disabled> d</li>"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn figure() -> Result<()> {
+        let mut content = Blueprint::parse("", "# a\n\n![b \"*c*\"](d.png)\n")?;
+        if let Paragraph::Figure(fig) = &mut content.root.subsections[0].body[0].node {
+            fig.number = 2;
+        }
+        let page = Page {
+            content,
+            ..Default::default()
+        };
+        let out = Engine::new(&Default::default())?.render(&page)?;
+        assert!(out.contains("<img src=\"d.png\" alt=\"b &quot;c&quot;\" />"));
+        assert!(out.contains(
+            "<figcaption>Figure 2: b &quot;<span class=\"m-text m-strong\">c</span>&quot;</figcaption>"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn reference() -> Result<()> {
+        let mut content = Blueprint::parse("", "# a\n\n[[req:b]] [[req:c]]\n")?;
+        if let Paragraph::Spans(s) = &mut content.root.subsections[0].body[0].node {
+            if let Span::Ref(r) = &mut s[0].node {
+                r.title = Some("B <1>".to_string());
+                r.link = Some("req_b.html".to_string());
+            }
+        }
+        let page = Page {
+            content,
+            ..Default::default()
+        };
+        let out = Engine::new(&Default::default())?.render(&page)?;
+        assert!(out.contains("<a href=\"req_b.html\">B &lt;1&gt;</a> [[req:c]]"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn attributes() -> Result<()> {
+        let page = Page {
+            attributes: [("owner".to_string(), "<ops>".to_string())].into(),
+            ..Default::default()
+        };
+        let out = Engine::new(&Default::default())?.render(&page)?;
+        assert!(out.contains("<tr><th>owner</th><td>&lt;ops&gt;</td></tr>"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn relations() -> Result<()> {
+        let page = Page {
+            relations: vec![
+                Related {
+                    label: "verifies".to_string(),
+                    color: "info".to_string(),
+                    directed: true,
+                    incoming: true,
+                    title: "T".to_string(),
+                    link: "bp_a.html#t".to_string(),
+                },
+                Related {
+                    label: "conflicts with".to_string(),
+                    color: "danger".to_string(),
+                    title: "s".to_string(),
+                    link: "req_s.html".to_string(),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let eng = Engine::new(&Default::default())?;
+        let out = eng.render(&page)?;
+        assert!(out.contains(
+            "<li>&larr; <span class=\"m-label m-info\">verifies</span> <a href=\"bp_a.html#t\">T</a></li>"
+        ));
+        assert!(out.contains("<li>&harr; <span class=\"m-label m-danger\">conflicts with</span>"));
+        let out = eng.render_index(
+            "trace.html",
+            "Traceability",
+            &crate::model::TraceReport {
+                matrix: crate::trace::Matrix {
+                    requirements: vec!["r1".to_string()],
+                    sections: vec![Default::default()],
+                    cells: vec![vec![vec!["satisfies".to_string()]]],
+                },
+                ..Default::default()
+            },
+        )?;
+        assert!(out.contains("<td><span class=\"m-label m-success\">satisfies</span></td>"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn meta() -> Result<()> {
+        let page = Page {
+            content: Blueprint::parse(
+                "",
+                "---\nid: NET-1\nauthor: <ops>\nstatus: review\n---\n# a\n",
+            )?,
+            ..Default::default()
+        };
+        let out = Engine::new(&Default::default())?.render(&page)?;
+        assert!(out.contains(
+            "<p class=\"m-text m-dim\">NET-1 by &lt;ops&gt; <span class=\"m-label m-flat m-default\">review</span></p>"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn anchors() -> Result<()> {
+        let page = Page {
+            content: Blueprint::parse("", "# a\n\n## b {#c} [d]\n")?,
+            origin: Some(Origin {
+                title: "E".to_string(),
+                link: "bp_e.html#a".to_string(),
+            }),
+            ..Default::default()
+        };
+        let out = Engine::new(&Default::default())?.render(&page)?;
+        assert!(out.contains("<h1 id=\"a\">a <a class=\"m-text m-dim\" href=\"#a\""));
+        assert!(out.contains("<h2 id=\"c\">b <a class=\"m-text m-dim\" href=\"#c\""));
+        assert!(out.contains("Defined in <a href=\"bp_e.html#a\">E</a>"));
+
+        Ok(())
+    }
 }
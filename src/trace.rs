@@ -0,0 +1,333 @@
+use crate::markup::*;
+
+/// A section taking part in the traceability graph
+#[derive(
+    Clone, Default, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
+pub struct Node {
+    pub page: String,
+    pub page_file: String,
+    /// Blueprint the section was read from, which differs from `page` when it was included
+    pub source: String,
+    pub title: String,
+    /// Anchor of the section within its page
+    pub anchor: String,
+    pub location: Location,
+}
+
+impl Node {
+    /// Whether both nodes come from the same section, whichever of its tags they were read from
+    pub fn is_section(&self, other: &Node) -> bool {
+        self.page == other.page && self.anchor == other.anchor
+    }
+}
+
+/// A problem found while cross-checking `[?X]` declarations against the links to them
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum Issue {
+    /// A requirement that no section satisfies
+    Unsatisfied { name: String, node: Node },
+    /// A link naming a requirement that is never declared
+    Dangling {
+        name: String,
+        relation: String,
+        node: Node,
+    },
+}
+
+impl std::fmt::Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Issue::Unsatisfied { name, node } => write!(
+                f,
+                "unsatisfied requirement {} (declared in \"{}\")",
+                name, node.title
+            ),
+            Issue::Dangling {
+                name,
+                relation,
+                node,
+            } => write!(
+                f,
+                "unknown requirement {} ({} link in \"{}\")",
+                name, relation, node.title
+            ),
+        }
+    }
+}
+
+/// Requirements × linking sections, as rendered on `trace.html`
+#[derive(Clone, Default, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Matrix {
+    pub requirements: Vec<String>,
+    pub sections: Vec<Node>,
+    /// Names of the relations linking each section to each requirement
+    pub cells: Vec<Vec<Vec<String>>>,
+}
+
+/// Graph of requirement declarations and the sections linking to them, by relation
+#[derive(Clone, Default, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Trace {
+    pub declarations: std::collections::BTreeMap<String, Vec<Node>>,
+    /// Linking sections by relation, then by target requirement
+    pub links: std::collections::BTreeMap<String, std::collections::BTreeMap<String, Vec<Node>>>,
+    /// Relations whose links satisfy their target
+    pub satisfying: std::collections::BTreeSet<String>,
+}
+
+impl Trace {
+    pub fn new<'a, T: IntoIterator<Item = &'a Blueprint>>(
+        pages: T,
+        config: &crate::config::Config,
+    ) -> Trace {
+        let mut out = Trace {
+            links: config
+                .relations
+                .0
+                .keys()
+                .map(|name| (name.clone(), Default::default()))
+                .collect(),
+            satisfying: config
+                .relations
+                .0
+                .iter()
+                .filter(|(_, r)| r.satisfies)
+                .map(|(name, _)| name.clone())
+                .collect(),
+            ..Default::default()
+        };
+        for bp in pages {
+            out.collect(bp, &bp.root, config);
+        }
+        for nodes in out
+            .declarations
+            .values_mut()
+            .chain(out.links.values_mut().flat_map(|l| l.values_mut()))
+        {
+            nodes.sort_by(|a, b| (&a.page, a.location.start).cmp(&(&b.page, b.location.start)));
+        }
+        out
+    }
+
+    fn collect(&mut self, bp: &Blueprint, sec: &Section, config: &crate::config::Config) {
+        for tag in &sec.heading.tags {
+            let node = Node {
+                page: bp.name.clone(),
+                page_file: config.output.page_file(&bp.name),
+                source: sec.source(&bp.name).to_string(),
+                title: sec.title(),
+                anchor: sec.heading.anchor.clone(),
+                location: tag.location,
+            };
+            let nodes = match tag.sigil {
+                None => continue,
+                Some(sigil) if sigil == config.tags.requirement_sigil => {
+                    self.declarations.entry(tag.name.clone()).or_default()
+                }
+                Some(sigil) => match config.relations.by_sigil(sigil) {
+                    Some((relation, _)) => self
+                        .links
+                        .entry(relation.clone())
+                        .or_default()
+                        .entry(tag.name.clone())
+                        .or_default(),
+                    None => continue,
+                },
+            };
+            nodes.push(node);
+        }
+        for sub in &sec.subsections {
+            self.collect(bp, sub, config);
+        }
+    }
+
+    /// Sections linking to the named requirement through the given relation
+    pub fn linked_by(&self, relation: &str, name: &str) -> &[Node] {
+        self.links
+            .get(relation)
+            .and_then(|l| l.get(name))
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Sections satisfying the named requirement, through any satisfying relation
+    pub fn satisfied_by(&self, name: &str) -> Vec<&Node> {
+        self.satisfying
+            .iter()
+            .flat_map(|relation| self.linked_by(relation, name))
+            .collect()
+    }
+
+    /// Unsatisfied requirements ordered by name, followed by dangling links ordered by relation
+    /// and name
+    pub fn issues(&self) -> Vec<Issue> {
+        let unsatisfied = self
+            .declarations
+            .iter()
+            .filter(|(name, _)| self.satisfied_by(name).is_empty())
+            .flat_map(|(name, nodes)| {
+                nodes.iter().map(|node| Issue::Unsatisfied {
+                    name: name.clone(),
+                    node: node.clone(),
+                })
+            });
+        let dangling = self.links.iter().flat_map(|(relation, links)| {
+            links
+                .iter()
+                .filter(|(name, _)| !self.declarations.contains_key(*name))
+                .flat_map(move |(name, nodes)| {
+                    nodes.iter().map(move |node| Issue::Dangling {
+                        name: name.clone(),
+                        relation: relation.clone(),
+                        node: node.clone(),
+                    })
+                })
+        });
+        unsatisfied.chain(dangling).collect()
+    }
+
+    pub fn matrix(&self) -> Matrix {
+        let requirements = self.declarations.keys().cloned().collect::<Vec<_>>();
+        let mut sections = self
+            .links
+            .values()
+            .flatten()
+            .filter(|(name, _)| self.declarations.contains_key(*name))
+            .flat_map(|(_, nodes)| nodes.iter().cloned())
+            .collect::<std::collections::BTreeSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        // Nodes differ by tag location, but each section gets a single column
+        sections.dedup_by(|a, b| a.is_section(b));
+        let cells = requirements
+            .iter()
+            .map(|name| {
+                sections
+                    .iter()
+                    .map(|s| {
+                        self.links
+                            .keys()
+                            .filter(|relation| {
+                                self.linked_by(relation, name)
+                                    .iter()
+                                    .any(|n| n.is_section(s))
+                            })
+                            .cloned()
+                            .collect()
+                    })
+                    .collect()
+            })
+            .collect();
+        Matrix {
+            requirements,
+            sections,
+            cells,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    fn make_trace() -> Result<Trace> {
+        let pages = [
+            Blueprint::parse("a.bp", "# A\n## R1 [?r1]\n## R2 [?r2]")?,
+            Blueprint::parse("b.bp", "# B [=r1]\n## C [=r1,=r3]")?,
+        ];
+        Ok(Trace::new(&pages, &Default::default()))
+    }
+
+    fn make_config() -> Result<crate::config::Config> {
+        Ok(toml::from_str(
+            r#"
+            [relations.satisfies]
+            sigil = "="
+            label = "satisfies"
+            satisfies = true
+
+            [relations.verifies]
+            sigil = "!"
+            label = "verifies"
+            color = "info"
+
+            [relations.conflicts-with]
+            sigil = "~"
+            label = "conflicts with"
+            direction = "undirected"
+            dangling = "deny"
+            "#,
+        )?)
+    }
+
+    #[test]
+    fn issues() -> Result<()> {
+        let issues = make_trace()?.issues();
+        assert_eq!(issues.len(), 2);
+        assert!(matches!(&issues[0], Issue::Unsatisfied { name, .. } if name == "r2"));
+        assert!(
+            matches!(&issues[1], Issue::Dangling { name, node, .. } if name == "r3" && node.title == "C")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn matrix() -> Result<()> {
+        let matrix = make_trace()?.matrix();
+        assert_eq!(matrix.requirements, vec!["r1", "r2"]);
+        assert_eq!(
+            matrix
+                .sections
+                .iter()
+                .map(|s| s.title.as_str())
+                .collect::<Vec<_>>(),
+            vec!["B", "C"]
+        );
+        let satisfies = vec!["satisfies".to_string()];
+        assert_eq!(
+            matrix.cells,
+            vec![vec![satisfies.clone(), satisfies], vec![vec![], vec![]]]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn relations() -> Result<()> {
+        let pages = [Blueprint::parse(
+            "a.bp",
+            "# A [?a,~b]\n## B [?b,!a,=a]\n## C [!b,!x,=c]\n",
+        )?];
+        let trace = Trace::new(&pages, &make_config()?);
+        assert_eq!(
+            trace
+                .linked_by("verifies", "a")
+                .iter()
+                .map(|n| n.title.as_str())
+                .collect::<Vec<_>>(),
+            vec!["B"]
+        );
+        assert_eq!(trace.linked_by("conflicts-with", "b")[0].title, "A");
+        assert_eq!(
+            trace
+                .issues()
+                .iter()
+                .map(|i| i.to_string())
+                .collect::<Vec<_>>(),
+            vec![
+                "unsatisfied requirement b (declared in \"B\")",
+                "unknown requirement c (satisfies link in \"C\")",
+                "unknown requirement x (verifies link in \"C\")",
+            ]
+        );
+        let matrix = trace.matrix();
+        assert_eq!(
+            matrix.cells[0][1],
+            vec!["satisfies".to_string(), "verifies".to_string()]
+        );
+
+        Ok(())
+    }
+}